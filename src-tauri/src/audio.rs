@@ -0,0 +1,389 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    FromSample, SampleFormat, SizedSample, StreamConfig,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    synth::{Instrument, Synth, SynthSettings},
+    wav::WavWriter,
+};
+
+/// Frames rendered at once by sinks that keep time themselves.
+const BLOCK_FRAMES: usize = 256;
+
+/// Where the internal synth plays to.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AudioSinkSettings {
+    /// The system audio device with this name, or the default device.
+    Device { name: Option<String> },
+    /// Renders in real time and drops the audio, for running without an
+    /// audio device.
+    Null,
+    /// Renders in real time into a WAV file.
+    File { path: PathBuf },
+}
+
+impl Default for AudioSinkSettings {
+    fn default() -> Self {
+        AudioSinkSettings::Device { name: None }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+pub enum AudioError {
+    InvalidSettings,
+    NoDevice,
+    /// The audio device could not be opened or failed while playing.
+    Device(String),
+    Io(String),
+}
+
+impl std::fmt::Display for AudioError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AudioError::InvalidSettings => write!(f, "invalid synth settings"),
+            AudioError::NoDevice => write!(f, "audio device not found"),
+            AudioError::Device(err) => write!(f, "error in audio device: {}", err),
+            AudioError::Io(err) => write!(f, "error while writing audio: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for AudioError {}
+
+impl From<std::io::Error> for AudioError {
+    fn from(err: std::io::Error) -> Self {
+        AudioError::Io(err.to_string())
+    }
+}
+
+fn device_error(err: impl std::fmt::Display) -> AudioError {
+    AudioError::Device(err.to_string())
+}
+
+/// Pulls audio from the synth at its own pace, on a thread of its own, until
+/// closed.
+pub trait AudioSink: Send {
+    /// Stops pulling audio and closes whatever the sink plays to. Fails with
+    /// the first error the sink ran into while playing, if any.
+    fn close(self: Box<Self>) -> Result<(), AudioError>;
+}
+
+/// Names of the system's audio output devices.
+pub fn output_devices() -> Result<Vec<String>, AudioError> {
+    let devices = cpal::default_host()
+        .output_devices()
+        .map_err(device_error)?;
+    Ok(devices.filter_map(|device| device.name().ok()).collect())
+}
+
+/// Plays to a system audio device, which asks for audio as it needs it.
+struct DeviceSink {
+    stop: mpsc::Sender<()>,
+    thread: Option<JoinHandle<()>>,
+    /// First error reported by the stream while playing.
+    error: Arc<Mutex<Option<AudioError>>>,
+}
+
+impl DeviceSink {
+    fn open(name: Option<String>, synth: Arc<Mutex<Synth>>) -> Result<Self, AudioError> {
+        let (opened_tx, opened_rx) = mpsc::channel();
+        let (stop, stop_rx) = mpsc::channel::<()>();
+        let error = Arc::new(Mutex::new(None));
+        let stream_error = error.clone();
+
+        // Streams cannot move between threads on every platform, so the
+        // stream lives on a thread that does nothing but keep it open.
+        let thread = thread::spawn(move || {
+            let _stream = match open_stream(name, synth, stream_error) {
+                Ok(stream) => stream,
+                Err(err) => {
+                    opened_tx.send(Err(err)).ok();
+                    return;
+                }
+            };
+            opened_tx.send(Ok(())).ok();
+            // Plays until told to stop, or until the sink is dropped.
+            stop_rx.recv().ok();
+        });
+
+        opened_rx
+            .recv()
+            .map_err(|_| AudioError::Device("audio thread stopped".to_string()))??;
+        Ok(DeviceSink {
+            stop,
+            thread: Some(thread),
+            error,
+        })
+    }
+}
+
+impl AudioSink for DeviceSink {
+    fn close(mut self: Box<Self>) -> Result<(), AudioError> {
+        self.stop.send(()).ok();
+        if let Some(thread) = self.thread.take() {
+            thread
+                .join()
+                .map_err(|_| AudioError::Device("audio thread panicked".to_string()))?;
+        }
+        match self.error.lock().unwrap().take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+}
+
+fn open_stream(
+    name: Option<String>,
+    synth: Arc<Mutex<Synth>>,
+    error: Arc<Mutex<Option<AudioError>>>,
+) -> Result<cpal::Stream, AudioError> {
+    let host = cpal::default_host();
+    let device = match name {
+        Some(name) => host
+            .output_devices()
+            .map_err(device_error)?
+            .find(|device| device.name().is_ok_and(|device_name| device_name == name)),
+        None => host.default_output_device(),
+    }
+    .ok_or(AudioError::NoDevice)?;
+
+    let config = device.default_output_config().map_err(device_error)?;
+    synth
+        .lock()
+        .unwrap()
+        .set_sample_rate(config.sample_rate().0);
+
+    let stream = match config.sample_format() {
+        SampleFormat::F32 => build_stream::<f32>(&device, &config.config(), synth, error)?,
+        SampleFormat::I16 => build_stream::<i16>(&device, &config.config(), synth, error)?,
+        SampleFormat::U16 => build_stream::<u16>(&device, &config.config(), synth, error)?,
+        format => {
+            return Err(AudioError::Device(format!(
+                "unsupported sample format {:?}",
+                format
+            )))
+        }
+    };
+    stream.play().map_err(device_error)?;
+    Ok(stream)
+}
+
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    synth: Arc<Mutex<Synth>>,
+    error: Arc<Mutex<Option<AudioError>>>,
+) -> Result<cpal::Stream, AudioError>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let channels = config.channels as usize;
+    let mut stereo = vec![];
+
+    device
+        .build_output_stream(
+            config,
+            move |data: &mut [T], _| {
+                stereo.resize(data.len() / channels * 2, 0.0);
+                synth.lock().unwrap().render(&mut stereo);
+
+                for (frame, samples) in data.chunks_exact_mut(channels).zip(stereo.chunks_exact(2))
+                {
+                    // Mono devices get both sides mixed; channels beyond the
+                    // second are left silent.
+                    for (channel, sample) in frame.iter_mut().enumerate() {
+                        let value = match (channels, channel) {
+                            (1, _) => (samples[0] + samples[1]) / 2.0,
+                            (_, 0 | 1) => samples[channel],
+                            _ => 0.0,
+                        };
+                        *sample = T::from_sample(value);
+                    }
+                }
+            },
+            move |err| {
+                error.lock().unwrap().get_or_insert(device_error(err));
+            },
+            None,
+        )
+        .map_err(device_error)
+}
+
+/// Keeps time itself, rendering block by block in real time, and writes the
+/// audio to a WAV file or drops it.
+struct ClockedSink {
+    stopped: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Result<(), AudioError>>>,
+}
+
+impl ClockedSink {
+    fn start(synth: Arc<Mutex<Synth>>, sample_rate: u32, mut file: Option<WavWriter>) -> Self {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let thread_stopped = stopped.clone();
+
+        let thread = thread::spawn(move || {
+            let start = Instant::now();
+            let mut frames = 0u64;
+            let mut block = vec![0.0; BLOCK_FRAMES * 2];
+            let mut result = Ok(());
+
+            while !thread_stopped.load(Ordering::Relaxed) {
+                synth.lock().unwrap().render(&mut block);
+                if let Some(file) = &mut file {
+                    // A file that cannot take any more still gets finished,
+                    // so that what was written plays.
+                    result = file.write(&block);
+                    if result.is_err() {
+                        break;
+                    }
+                }
+
+                frames += BLOCK_FRAMES as u64;
+                let due = start + Duration::from_secs_f64(frames as f64 / sample_rate as f64);
+                thread::sleep(due.saturating_duration_since(Instant::now()));
+            }
+
+            if let Some(file) = file {
+                file.finish()?;
+            }
+            Ok(result?)
+        });
+
+        ClockedSink {
+            stopped,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl AudioSink for ClockedSink {
+    fn close(mut self: Box<Self>) -> Result<(), AudioError> {
+        self.stopped.store(true, Ordering::Relaxed);
+        match self.thread.take().map(JoinHandle::join) {
+            Some(Ok(result)) => result,
+            Some(Err(_)) => Err(AudioError::Io("audio thread panicked".to_string())),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The internal synth playing live to an audio sink, as the audio
+/// counterpart of the MIDI output.
+pub struct AudioOut {
+    synth: Arc<Mutex<Synth>>,
+    sink: Box<dyn AudioSink>,
+    settings: AudioSinkSettings,
+}
+
+impl AudioOut {
+    pub fn open(
+        settings: AudioSinkSettings,
+        synth_settings: &SynthSettings,
+        instrument: Instrument,
+    ) -> Result<Self, AudioError> {
+        if !synth_settings.is_valid() {
+            return Err(AudioError::InvalidSettings);
+        }
+        let synth = Arc::new(Mutex::new(Synth::new(synth_settings, instrument)));
+        let sample_rate = synth_settings.sample_rate;
+
+        let sink: Box<dyn AudioSink> = match &settings {
+            AudioSinkSettings::Device { name } => {
+                Box::new(DeviceSink::open(name.clone(), synth.clone())?)
+            }
+            AudioSinkSettings::Null => {
+                Box::new(ClockedSink::start(synth.clone(), sample_rate, None))
+            }
+            AudioSinkSettings::File { path } => {
+                let file = WavWriter::create(path, sample_rate, 2)?;
+                Box::new(ClockedSink::start(synth.clone(), sample_rate, Some(file)))
+            }
+        };
+
+        Ok(AudioOut {
+            synth,
+            sink,
+            settings,
+        })
+    }
+
+    pub fn settings(&self) -> &AudioSinkSettings {
+        &self.settings
+    }
+
+    /// Plays a raw MIDI message.
+    pub fn send(&self, message: &[u8]) {
+        self.synth.lock().unwrap().handle(message);
+    }
+
+    /// Switches instruments. Notes that are sounding keep their instrument.
+    pub fn set_instrument(&self, instrument: Instrument) {
+        self.synth.lock().unwrap().set_instrument(instrument);
+    }
+
+    pub fn close(self) -> Result<(), AudioError> {
+        self.sink.close()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn file_sink_renders_in_real_time() {
+        let path = std::env::temp_dir().join(format!("audio-test-{}.wav", std::process::id()));
+        let settings = SynthSettings::default();
+        let audio_out = AudioOut::open(
+            AudioSinkSettings::File { path: path.clone() },
+            &settings,
+            Instrument::Piano,
+        )
+        .unwrap();
+
+        audio_out.send(&[0x90, 60, 100]);
+        thread::sleep(Duration::from_millis(200));
+        audio_out.send(&[0x80, 60, 0]);
+        audio_out.close().unwrap();
+
+        let data = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(&data[8..12], b"WAVE");
+        let data_len = u32::from_le_bytes(data[40..44].try_into().unwrap()) as usize;
+        assert_eq!(data_len, data.len() - 44);
+        // The 200 ms it ran for, as stereo 16-bit frames, less a block.
+        assert!(data_len >= (44_100 / 5 - BLOCK_FRAMES) * 4);
+        assert!(data[44..].iter().any(|byte| *byte != 0));
+    }
+
+    #[test]
+    fn rejects_invalid_synth_settings() {
+        let settings = SynthSettings {
+            sample_rate: 0,
+            ..SynthSettings::default()
+        };
+
+        assert!(matches!(
+            AudioOut::open(AudioSinkSettings::Null, &settings, Instrument::Piano),
+            Err(AudioError::InvalidSettings)
+        ));
+    }
+}
@@ -0,0 +1,98 @@
+use std::{thread, time::Duration};
+
+use midir::{MidiInput, MidiOutput};
+use serde::Serialize;
+use tauri::{AppHandle, Emitter};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceKind {
+    Input,
+    Output,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct MidiDeviceEvent {
+    pub kind: DeviceKind,
+    pub name: String,
+    /// Port id, as used by the connect commands.
+    pub index: String,
+}
+
+pub enum DeviceChange {
+    Added(MidiDeviceEvent),
+    Removed(MidiDeviceEvent),
+}
+
+fn input_ports(midi_in: &MidiInput) -> Vec<MidiDeviceEvent> {
+    midi_in
+        .ports()
+        .iter()
+        .map(|port| MidiDeviceEvent {
+            kind: DeviceKind::Input,
+            name: midi_in.port_name(port).unwrap_or_default(),
+            index: port.id(),
+        })
+        .collect()
+}
+
+fn output_ports(midi_out: &MidiOutput) -> Vec<MidiDeviceEvent> {
+    midi_out
+        .ports()
+        .iter()
+        .map(|port| MidiDeviceEvent {
+            kind: DeviceKind::Output,
+            name: midi_out.port_name(port).unwrap_or_default(),
+            index: port.id(),
+        })
+        .collect()
+}
+
+/// Watches for MIDI ports appearing and disappearing, on a thread of its
+/// own for the lifetime of the app.
+///
+/// Every change is emitted as a `midi_device_added` or `midi_device_removed`
+/// event and then passed to `on_change`.
+pub fn watch(app: AppHandle, mut on_change: impl FnMut(&DeviceChange) + Send + 'static) {
+    // The app's own clients are consumed while connected, so the watcher
+    // lists ports through clients of its own.
+    let midi_in =
+        MidiInput::new("midir device watcher input").expect("error while creating midi input");
+    let midi_out =
+        MidiOutput::new("midir device watcher output").expect("error while creating midi output");
+
+    thread::spawn(move || {
+        let list_ports = || {
+            let mut ports = input_ports(&midi_in);
+            ports.extend(output_ports(&midi_out));
+            ports
+        };
+        let mut known = list_ports();
+
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            let ports = list_ports();
+
+            let removed = known.iter().filter(|port| !ports.contains(port));
+            let added = ports.iter().filter(|port| !known.contains(port));
+            let changes = removed
+                .cloned()
+                .map(DeviceChange::Removed)
+                .chain(added.cloned().map(DeviceChange::Added))
+                .collect::<Vec<_>>();
+
+            for change in &changes {
+                let (event, device) = match change {
+                    DeviceChange::Added(device) => ("midi_device_added", device),
+                    DeviceChange::Removed(device) => ("midi_device_removed", device),
+                };
+                app.emit(event, device)
+                    .expect("error while emitting midi device event");
+                on_change(change);
+            }
+            known = ports;
+        }
+    });
+}
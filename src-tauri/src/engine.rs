@@ -0,0 +1,730 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter};
+
+use crate::{
+    playback::{Song, TrackSelection},
+    wait_mode::{WaitMode, WaitModeSettings},
+};
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum PlaybackStatus {
+    Playing,
+    Paused,
+    /// Holding at a chord until the student plays it on the live input.
+    Waiting,
+    Stopped,
+}
+
+#[derive(Debug, Serialize, Clone, Copy)]
+pub struct PlaybackStateEvent {
+    status: PlaybackStatus,
+    position_ms: u64,
+    duration_ms: u64,
+    speed: f64,
+    transpose: i8,
+}
+
+/// Tempo scaling and transposition applied while a file plays.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct PlaybackSettings {
+    /// Playback speed relative to the file's own tempo, e.g. 0.5 for half speed.
+    pub speed: f64,
+    /// Semitones added to every note, except on the drum channel.
+    pub transpose: i8,
+}
+
+impl Default for PlaybackSettings {
+    fn default() -> Self {
+        PlaybackSettings {
+            speed: 1.0,
+            transpose: 0,
+        }
+    }
+}
+
+impl PlaybackSettings {
+    pub const SPEED_RANGE: std::ops::RangeInclusive<f64> = 0.25..=2.0;
+    pub const TRANSPOSE_RANGE: std::ops::RangeInclusive<i8> = -24..=24;
+}
+
+/// A loop boundary, given either as a time or as a bar number counted from 1.
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum LoopPoint {
+    Ms(u64),
+    Bar(u32),
+}
+
+#[derive(Debug, Serialize, Clone, Copy)]
+pub struct LoopIterationEvent {
+    iteration: u32,
+    start_ms: u64,
+    end_ms: u64,
+}
+
+/// The song position jumping elsewhere instead of playing on, by seeking or
+/// by a loop starting over. Positions are in microseconds.
+#[derive(Debug, Clone, Copy)]
+pub struct Jump {
+    pub from: u64,
+    pub to: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PlaybackLoop {
+    /// Song positions in microseconds; `end` is exclusive.
+    start: u64,
+    end: u64,
+    /// Real time to wait before each repetition starts.
+    gap: Duration,
+    iteration: u32,
+}
+
+/// MIDI channel 10, which General MIDI reserves for percussion.
+const DRUM_CHANNEL: u8 = 9;
+
+/// Transposes a key, leaving drums alone, or returns `None` if it would fall
+/// outside the MIDI range.
+pub fn transpose_key(channel: u8, key: u8, transpose: i8) -> Option<u8> {
+    if channel == DRUM_CHANNEL {
+        return Some(key);
+    }
+    u8::try_from(key as i16 + transpose as i16)
+        .ok()
+        .filter(|key| *key <= 127)
+}
+
+/// Transposes a note message, or returns `None` if the note would fall
+/// outside the MIDI range. Other messages are returned unchanged.
+fn transpose_message(message: [u8; 3], transpose: i8) -> Option<[u8; 3]> {
+    match message[0] & 0xF0 {
+        0x80 | 0x90 | 0xA0 => {
+            let key = transpose_key(message[0] & 0x0F, message[1], transpose)?;
+            Some([message[0], key, message[2]])
+        }
+        _ => Some(message),
+    }
+}
+
+/// Notes and pedals that have been sent to the output and not yet released.
+#[derive(Debug, Default)]
+struct SoundingNotes {
+    /// (channel, key in the file) to the key that was actually sent, so notes
+    /// are released correctly even if the transposition changes meanwhile.
+    notes: HashMap<(u8, u8), u8>,
+    pedal_channels: HashSet<u8>,
+}
+
+impl SoundingNotes {
+    /// Transposes a message about to be sent and keeps track of what it
+    /// leaves sounding.
+    fn send(&mut self, message: [u8; 3], transpose: i8) -> Option<[u8; 3]> {
+        let channel = message[0] & 0x0F;
+        let note = (channel, message[1]);
+        match message[0] & 0xF0 {
+            0x90 => {
+                let message = transpose_message(message, transpose)?;
+                self.notes.insert(note, message[1]);
+                Some(message)
+            }
+            0x80 => match self.notes.remove(&note) {
+                Some(key) => Some([message[0], key, message[2]]),
+                None => transpose_message(message, transpose),
+            },
+            0xA0 => match self.notes.get(&note) {
+                Some(key) => Some([message[0], *key, message[2]]),
+                None => transpose_message(message, transpose),
+            },
+            0xB0 if message[1] == 64 => {
+                if message[2] >= 64 {
+                    self.pedal_channels.insert(channel);
+                } else {
+                    self.pedal_channels.remove(&channel);
+                }
+                Some(message)
+            }
+            _ => Some(message),
+        }
+    }
+
+    /// Messages that silence everything still sounding.
+    fn release_all(&mut self) -> Vec<[u8; 3]> {
+        let pedals_up = self
+            .pedal_channels
+            .drain()
+            .map(|channel| [0xB0 | channel, 64, 0]);
+        let notes_off = self
+            .notes
+            .drain()
+            .map(|((channel, _), key)| [0x80 | channel, key, 0]);
+
+        pedals_up.chain(notes_off).collect()
+    }
+}
+
+struct Transport {
+    status: PlaybackStatus,
+    /// Song position at `anchor`, in microseconds.
+    anchor_position: u64,
+    anchor: Instant,
+    /// Set when the position jumps, so the scheduler looks up its next event.
+    seeked: bool,
+    settings: PlaybackSettings,
+    playback_loop: Option<PlaybackLoop>,
+    wait_mode: Option<WaitMode>,
+    sounding: SoundingNotes,
+    /// Every jump since playback started, oldest first.
+    jumps: Vec<Jump>,
+}
+
+impl Transport {
+    fn position(&self) -> u64 {
+        match self.status {
+            PlaybackStatus::Playing => {
+                let elapsed = self.anchor.elapsed().as_micros() as f64;
+                self.anchor_position + (elapsed * self.settings.speed) as u64
+            }
+            _ => self.anchor_position,
+        }
+    }
+
+    /// Real time until the song reaches `position`.
+    fn time_until(&self, position: u64) -> Duration {
+        // The anchor lies in the future while waiting between loop repetitions.
+        let gap = self.anchor.saturating_duration_since(Instant::now());
+        let song_time = position.saturating_sub(self.position());
+        gap + Duration::from_micros((song_time as f64 / self.settings.speed).ceil() as u64)
+    }
+
+    fn set_position(&mut self, position: u64) {
+        self.anchor_position = position;
+        self.anchor = Instant::now();
+    }
+
+    fn jump(&mut self, position: u64) {
+        self.jumps.push(Jump {
+            from: self.position(),
+            to: position,
+        });
+        self.set_position(position);
+        self.seeked = true;
+    }
+
+    /// Jumps back to the start of the loop, holding there for its gap first.
+    fn restart_loop(&mut self) -> Option<LoopIterationEvent> {
+        let playback_loop = self.playback_loop.as_mut()?;
+        playback_loop.iteration += 1;
+
+        let event = LoopIterationEvent {
+            iteration: playback_loop.iteration,
+            start_ms: playback_loop.start / 1000,
+            end_ms: playback_loop.end / 1000,
+        };
+
+        self.jumps.push(Jump {
+            from: playback_loop.end,
+            to: playback_loop.start,
+        });
+        self.anchor_position = playback_loop.start;
+        self.anchor = Instant::now() + playback_loop.gap;
+        self.seeked = true;
+        Some(event)
+    }
+}
+
+struct Shared {
+    transport: Mutex<Transport>,
+    wake: Condvar,
+    song: Song,
+    tracks: TrackSelection,
+    duration: u64,
+}
+
+impl Shared {
+    fn resolve(&self, point: LoopPoint, is_end: bool) -> Option<u64> {
+        match point {
+            LoopPoint::Ms(ms) => Some((ms * 1000).min(self.duration)),
+            // The end of a loop is the end of its last bar.
+            LoopPoint::Bar(bar) if bar > 0 => {
+                let index = bar as usize - 1 + is_end as usize;
+                match self.song.bars.get(index) {
+                    Some(time) => Some(*time),
+                    None if is_end && index == self.song.bars.len() => Some(self.duration),
+                    None => None,
+                }
+            }
+            LoopPoint::Bar(_) => None,
+        }
+    }
+
+    fn state(&self, transport: &Transport) -> PlaybackStateEvent {
+        PlaybackStateEvent {
+            status: transport.status,
+            position_ms: transport.position().min(self.duration) / 1000,
+            duration_ms: self.duration / 1000,
+            speed: transport.settings.speed,
+            transpose: transport.settings.transpose,
+        }
+    }
+}
+
+/// Reads the song position of a playback engine, so a recording can follow
+/// the same clock as the playback it is recorded over.
+#[derive(Clone)]
+pub struct PlaybackClock(Arc<Shared>);
+
+impl PlaybackClock {
+    /// Current song position in microseconds.
+    pub fn position(&self) -> u64 {
+        let transport = self.0.transport.lock().unwrap();
+        transport.position().min(self.0.duration)
+    }
+
+    /// Transposition playback is using, in semitones.
+    pub fn transpose(&self) -> i8 {
+        self.0.transport.lock().unwrap().settings.transpose
+    }
+}
+
+#[cfg(test)]
+impl PlaybackClock {
+    /// A clock standing still at `position`, as if playback were paused there
+    /// with notes transposed by `transpose`.
+    pub fn paused(position: u64, transpose: i8) -> Self {
+        PlaybackClock(Arc::new(Shared {
+            transport: Mutex::new(Transport {
+                status: PlaybackStatus::Paused,
+                anchor_position: position,
+                anchor: Instant::now(),
+                seeked: false,
+                settings: PlaybackSettings {
+                    transpose,
+                    ..PlaybackSettings::default()
+                },
+                playback_loop: None,
+                wait_mode: None,
+                sounding: SoundingNotes::default(),
+                jumps: vec![],
+            }),
+            wake: Condvar::new(),
+            song: Song {
+                timeline: vec![],
+                bars: vec![],
+            },
+            tracks: TrackSelection::default(),
+            duration: position,
+        }))
+    }
+}
+
+/// Plays a MIDI timeline on a background thread and lets it be paused,
+/// resumed, repositioned and stopped while it runs.
+///
+/// Events are emitted to the frontend the same way as before: falling notes
+/// as `future_piano_event` and the MIDI bytes as `future_piano_playback`.
+/// Note offs that silence the output are sent through the same channel so
+/// they arrive after any note ons the frontend is still holding back.
+pub struct PlaybackEngine {
+    app: AppHandle,
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl PlaybackEngine {
+    pub fn start(
+        app: AppHandle,
+        song: Song,
+        tracks: TrackSelection,
+        settings: PlaybackSettings,
+        wait_mode: Option<WaitModeSettings>,
+    ) -> Self {
+        let duration = song.timeline.last().map_or(0, |event| event.time);
+        let wait_mode = wait_mode.map(|wait_mode| WaitMode::new(&song, &tracks, wait_mode));
+        let shared = Arc::new(Shared {
+            transport: Mutex::new(Transport {
+                status: PlaybackStatus::Playing,
+                anchor_position: 0,
+                anchor: Instant::now(),
+                seeked: false,
+                settings,
+                playback_loop: None,
+                wait_mode,
+                sounding: SoundingNotes::default(),
+                jumps: vec![],
+            }),
+            wake: Condvar::new(),
+            song,
+            tracks,
+            duration,
+        });
+
+        let thread = {
+            let app = app.clone();
+            let shared = shared.clone();
+            thread::spawn(move || run_scheduler(app, shared))
+        };
+
+        let engine = PlaybackEngine {
+            app,
+            shared,
+            thread: Some(thread),
+        };
+        engine.emit_state(&engine.lock());
+        engine
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Transport> {
+        self.shared.transport.lock().unwrap()
+    }
+
+    fn emit_state(&self, transport: &Transport) {
+        self.app
+            .emit("playback_state", self.shared.state(transport))
+            .expect("Error while emitting playback state");
+    }
+
+    fn silence(&self, transport: &mut Transport) {
+        for message in transport.sounding.release_all() {
+            self.app
+                .emit("future_piano_playback", message)
+                .expect("Error while emitting piano event");
+        }
+    }
+
+    pub fn state(&self) -> PlaybackStateEvent {
+        self.shared.state(&self.lock())
+    }
+
+    /// Current song position in microseconds.
+    pub fn position(&self) -> u64 {
+        self.lock().position().min(self.shared.duration)
+    }
+
+    /// Number of jumps so far, which is the pass of the song being played,
+    /// together with the current song position.
+    pub fn pass_position(&self) -> (usize, u64) {
+        let transport = self.lock();
+        (
+            transport.jumps.len(),
+            transport.position().min(self.shared.duration),
+        )
+    }
+
+    pub fn jumps(&self) -> Vec<Jump> {
+        self.lock().jumps.clone()
+    }
+
+    pub fn clock(&self) -> PlaybackClock {
+        PlaybackClock(self.shared.clone())
+    }
+
+    pub fn settings(&self) -> PlaybackSettings {
+        self.lock().settings
+    }
+
+    pub fn song(&self) -> &Song {
+        &self.shared.song
+    }
+
+    pub fn tracks(&self) -> &TrackSelection {
+        &self.shared.tracks
+    }
+
+    pub fn pause(&self) -> bool {
+        let mut transport = self.lock();
+        if !matches!(
+            transport.status,
+            PlaybackStatus::Playing | PlaybackStatus::Waiting
+        ) {
+            return false;
+        }
+
+        let position = transport.position();
+        transport.status = PlaybackStatus::Paused;
+        transport.set_position(position);
+        self.silence(&mut transport);
+        self.shared.wake.notify_all();
+        self.emit_state(&transport);
+        true
+    }
+
+    pub fn resume(&self) -> bool {
+        let mut transport = self.lock();
+        if transport.status != PlaybackStatus::Paused {
+            return false;
+        }
+
+        let position = transport.position();
+        transport.status = PlaybackStatus::Playing;
+        transport.set_position(position);
+        self.shared.wake.notify_all();
+        self.emit_state(&transport);
+        true
+    }
+
+    pub fn seek(&self, position_ms: u64) -> bool {
+        let mut transport = self.lock();
+        if transport.status == PlaybackStatus::Stopped {
+            return false;
+        }
+
+        transport.jump((position_ms * 1000).min(self.shared.duration));
+        self.silence(&mut transport);
+        self.shared.wake.notify_all();
+        self.emit_state(&transport);
+        true
+    }
+
+    /// Changes speed and transposition without interrupting playback.
+    pub fn set_settings(&self, settings: PlaybackSettings) {
+        let mut transport = self.lock();
+
+        // Re-anchor so the new speed only applies from the current position.
+        let position = transport.position();
+        transport.set_position(position);
+        transport.settings = settings;
+
+        self.shared.wake.notify_all();
+        self.emit_state(&transport);
+    }
+
+    /// Loops the region between `start` and `end`, jumping to its start if
+    /// playback is currently outside of it.
+    pub fn set_loop(&self, start: LoopPoint, end: LoopPoint, gap_ms: u64) -> bool {
+        let (Some(start), Some(end)) = (
+            self.shared.resolve(start, false),
+            self.shared.resolve(end, true),
+        ) else {
+            return false;
+        };
+        if start >= end {
+            return false;
+        }
+
+        let mut transport = self.lock();
+        if transport.status == PlaybackStatus::Stopped {
+            return false;
+        }
+
+        transport.playback_loop = Some(PlaybackLoop {
+            start,
+            end,
+            gap: Duration::from_millis(gap_ms),
+            iteration: 0,
+        });
+
+        let position = transport.position();
+        if !(start..end).contains(&position) {
+            transport.jump(start);
+            self.silence(&mut transport);
+            self.emit_state(&transport);
+        }
+        self.shared.wake.notify_all();
+        true
+    }
+
+    pub fn clear_loop(&self) -> bool {
+        let mut transport = self.lock();
+        let cleared = transport.playback_loop.take().is_some();
+        self.shared.wake.notify_all();
+        cleared
+    }
+
+    /// Turns wait mode on with the given settings, or off with `None`.
+    pub fn set_wait_mode(&self, settings: Option<WaitModeSettings>) {
+        let mut transport = self.lock();
+        let position = transport.position();
+
+        transport.wait_mode = settings.map(|settings| {
+            let mut wait_mode = WaitMode::new(&self.shared.song, &self.shared.tracks, settings);
+            wait_mode.rewind(position);
+            wait_mode
+        });
+
+        if transport.status == PlaybackStatus::Waiting {
+            transport.status = PlaybackStatus::Playing;
+            transport.set_position(position);
+            self.emit_state(&transport);
+        }
+        self.shared.wake.notify_all();
+    }
+
+    /// Passes a key played on the live input to wait mode, continuing
+    /// playback once the chord it is waiting for has been played.
+    pub fn note_input(&self, key: u8, is_pressed: bool) {
+        let mut transport = self.lock();
+        let is_waiting = transport.status == PlaybackStatus::Waiting;
+        let Some(wait_mode) = transport.wait_mode.as_mut() else {
+            return;
+        };
+
+        if wait_mode.note_input(key, is_pressed) && is_waiting {
+            wait_mode.advance();
+            let position = transport.position();
+            transport.status = PlaybackStatus::Playing;
+            transport.set_position(position);
+            self.shared.wake.notify_all();
+            self.emit_state(&transport);
+        }
+    }
+
+    pub fn stop(mut self) {
+        {
+            let mut transport = self.lock();
+            if transport.status != PlaybackStatus::Stopped {
+                transport.status = PlaybackStatus::Stopped;
+                self.shared.wake.notify_all();
+            }
+        }
+
+        if let Some(thread) = self.thread.take() {
+            thread.join().expect("Error while joining playback thread");
+        }
+    }
+}
+
+fn run_scheduler(app: AppHandle, shared: Arc<Shared>) {
+    let timeline = &shared.song.timeline;
+    let tracks = &shared.tracks;
+    let mut next = 0;
+
+    loop {
+        let mut transport = shared.transport.lock().unwrap();
+
+        // Wait until the next event is due, or playback stops.
+        loop {
+            if transport.seeked {
+                let position = transport.position();
+                next = timeline.partition_point(|event| event.time < position);
+                if let Some(wait_mode) = transport.wait_mode.as_mut() {
+                    wait_mode.rewind(position);
+                }
+                if transport.status == PlaybackStatus::Waiting {
+                    transport.status = PlaybackStatus::Playing;
+                    transport.set_position(position);
+                }
+                transport.seeked = false;
+            }
+
+            match transport.status {
+                PlaybackStatus::Stopped => break,
+                PlaybackStatus::Paused | PlaybackStatus::Waiting => {
+                    transport = shared.wake.wait(transport).unwrap();
+                }
+                PlaybackStatus::Playing => {
+                    let loop_end = transport.playback_loop.map(|region| region.end);
+
+                    if loop_end.is_some_and(|end| transport.position() >= end) {
+                        for message in transport.sounding.release_all() {
+                            app.emit("future_piano_playback", message)
+                                .expect("Error while emitting piano event");
+                        }
+                        if let Some(iteration) = transport.restart_loop() {
+                            app.emit("loop_iteration", iteration)
+                                .expect("Error while emitting loop iteration");
+                        }
+                        continue;
+                    }
+
+                    // Events at or after the loop end belong to the next pass.
+                    let next_event = timeline
+                        .get(next)
+                        .map(|event| event.time)
+                        .filter(|time| loop_end.is_none_or(|end| *time < end));
+
+                    let next_chord = transport
+                        .wait_mode
+                        .as_ref()
+                        .and_then(WaitMode::next_chord_time)
+                        .filter(|time| loop_end.is_none_or(|end| *time < end));
+
+                    // Hold at a chord before playing anything that starts with it.
+                    if let Some(chord_time) = next_chord.filter(|time| {
+                        transport.position() >= *time
+                            && next_event.is_none_or(|event| *time <= event)
+                    }) {
+                        let transpose = transport.settings.transpose;
+                        let waiting = transport.wait_mode.as_mut().and_then(|wait_mode| {
+                            wait_mode.start_waiting(|channel, key| {
+                                transpose_key(channel, key, transpose)
+                            })
+                        });
+
+                        if let Some(waiting) = waiting {
+                            transport.status = PlaybackStatus::Waiting;
+                            transport.anchor_position = chord_time;
+                            app.emit("waiting_for_notes", waiting)
+                                .expect("Error while emitting waiting for notes");
+                            app.emit("playback_state", shared.state(&transport))
+                                .expect("Error while emitting playback state");
+                        }
+                        continue;
+                    }
+
+                    let targets = [next_event, next_chord, loop_end];
+                    let Some(target) = targets.into_iter().flatten().min() else {
+                        let position = transport.position();
+                        transport.status = PlaybackStatus::Stopped;
+                        transport.set_position(position);
+                        break;
+                    };
+
+                    let wait = transport.time_until(target);
+                    if wait.is_zero() {
+                        if next_event == Some(target) {
+                            break;
+                        }
+                        continue;
+                    }
+                    transport = shared.wake.wait_timeout(transport, wait).unwrap().0;
+                }
+            }
+        }
+
+        if transport.status == PlaybackStatus::Stopped {
+            for message in transport.sounding.release_all() {
+                app.emit("future_piano_playback", message)
+                    .expect("Error while emitting piano event");
+            }
+            app.emit("playback_state", shared.state(&transport))
+                .expect("Error while emitting playback state");
+            return;
+        }
+
+        let event = &timeline[next];
+        next += 1;
+
+        if !tracks.is_played(event.track) {
+            continue;
+        }
+
+        let PlaybackSettings { speed, transpose } = transport.settings;
+
+        if tracks.is_displayed(event.track) {
+            if let Some(message) = transpose_message(event.message, transpose) {
+                app.emit("future_piano_event", event.to_packed(message, speed))
+                    .expect("Error while emitting piano event");
+            }
+        }
+
+        // In wait mode the student plays their own notes.
+        let is_student_note = transport
+            .wait_mode
+            .as_ref()
+            .is_some_and(|wait_mode| wait_mode.is_student_note(event));
+
+        if tracks.is_audible(event.track) && !is_student_note {
+            if let Some(message) = transport.sounding.send(event.message, transpose) {
+                app.emit("future_piano_playback", message)
+                    .expect("Error while emitting piano event");
+            }
+        }
+    }
+}
@@ -1,329 +1,110 @@
 use std::{
-    collections::{HashMap, HashSet}, sync::{Arc, Mutex}, thread::{self, JoinHandle}, time::Instant
+    collections::HashSet, path::PathBuf, sync::{Arc, Mutex}, thread::{self, JoinHandle}, time::Instant
 };
 
 use midir::{Ignore, MidiInput, MidiInputConnection, MidiOutput, MidiOutputConnection};
 use midly::{
     live::LiveEvent,
-    num::{u15, u28},
-    Format, Header, Smf, Timing, Track, TrackEvent, TrackEventKind,
+    num::{u14, u4, u7},
+    MidiMessage, Smf,
 };
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use std::mem;
 use tauri::{AppHandle, Emitter, Manager, State};
 
-#[derive(Debug, Clone, Copy)]
-pub enum PianoKeyCode {
-    Eb0 = 15,
-    E0 = 16,
-    F0 = 17,
-    Gb0 = 18,
-    G0 = 19,
-    Ab0 = 20,
-    A1 = 21,
-    Bb1 = 22,
-    B1 = 23,
-    C1 = 24,
-    Db1 = 25,
-    D1 = 26,
-    Eb1 = 27,
-    E1 = 28,
-    F1 = 29,
-    Gb1 = 30,
-    G1 = 31,
-    Ab1 = 32,
-    A2 = 33,
-    Bb2 = 34,
-    B2 = 35,
-    C2 = 36,
-    Db2 = 37,
-    D2 = 38,
-    Eb2 = 39,
-    E2 = 40,
-    F2 = 41,
-    Gb2 = 42,
-    G2 = 43,
-    Ab2 = 44,
-    A3 = 45,
-    Bb3 = 46,
-    B3 = 47,
-    C3 = 48,
-    Db3 = 49,
-    D3 = 50,
-    Eb3 = 51,
-    E3 = 52,
-    F3 = 53,
-    Gb3 = 54,
-    G3 = 55,
-    Ab3 = 56,
-    A4 = 57,
-    Bb4 = 58,
-    B4 = 59,
-    C4 = 60,
-    Db4 = 61,
-    D4 = 62,
-    Eb4 = 63,
-    E4 = 64,
-    F4 = 65,
-    Gb4 = 66,
-    G4 = 67,
-    Ab4 = 68,
-    A5 = 69,
-    Bb5 = 70,
-    B5 = 71,
-    C5 = 72,
-    Db5 = 73,
-    D5 = 74,
-    Eb5 = 75,
-    E5 = 76,
-    F5 = 77,
-    Gb5 = 78,
-    G5 = 79,
-    Ab5 = 80,
-    A6 = 81,
-    Bb6 = 82,
-    B6 = 83,
-    C6 = 84,
-    Db6 = 85,
-    D6 = 86,
-    Eb6 = 87,
-    E6 = 88,
-    F6 = 89,
-    Gb6 = 90,
-    G6 = 91,
-    Ab6 = 92,
-    A7 = 93,
-    Bb7 = 94,
-    B7 = 95,
-    C7 = 96,
-    Db7 = 97,
-    D7 = 98,
-    Eb7 = 99,
-    E7 = 100,
-    F7 = 101,
-    Gb7 = 102,
-    G7 = 103,
-    Ab7 = 104,
-    A8 = 105,
-    Bb8 = 106,
-    B8 = 107,
-    C8 = 108,
-    Db8 = 109,
-    D8 = 110,
-    Eb8 = 111,
-    E8 = 112,
-    F8 = 113,
+mod audio;
+mod devices;
+mod engine;
+mod library;
+mod metronome;
+mod normalize;
+mod pedal;
+mod playback;
+mod quantize;
+mod recording;
+mod scoring;
+mod soundfont;
+mod synth;
+mod tempo;
+mod thru;
+mod wait_mode;
+mod wav;
+
+use audio::{AudioError, AudioOut, AudioSinkSettings};
+use devices::{DeviceChange, DeviceKind};
+use engine::{LoopPoint, PlaybackEngine, PlaybackSettings, PlaybackStateEvent};
+use library::{Library, LibraryError, RecordingEntry, RecordingMetadataUpdate};
+use metronome::{CountInSettings, Metronome, MetronomeSettings};
+use normalize::normalize_event_type;
+use pedal::{PedalOutput, PedalState};
+use playback::{track_info, MidiTrackInfo, Song, TrackSelection};
+use quantize::{quantize, quantized_path, QuantizeError, QuantizeSettings};
+use recording::{
+    default_recording_path, is_click_track, Recorder, RecordingSettings, SaveRecordingError,
+};
+use scoring::{align, score, ScoreError, ScoreReport, ScoredNote, ScoringSession, ScoringSettings};
+use soundfont::{PresetInfo, SoundFont, SoundFontError};
+use synth::{render_smf, rendered_path, Instrument, RenderError, SynthSettings};
+use thru::ThruSettings;
+use wait_mode::WaitModeSettings;
+use wav::WavWriter;
+
+/// A MIDI note number, covering the full 0–127 range.
+///
+/// Octaves use scientific pitch notation, so middle C (60) is `C4` and the
+/// lowest key on an 88-key piano (21) is `A0`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PianoKeyCode(u8);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidKeyCode(u8);
+
+impl std::fmt::Display for InvalidKeyCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid Piano Key Code: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidKeyCode {}
+
+impl TryFrom<u8> for PianoKeyCode {
+    type Error = InvalidKeyCode;
+
+    fn try_from(key: u8) -> Result<Self, Self::Error> {
+        match key {
+            0..=127 => Ok(PianoKeyCode(key)),
+            _ => Err(InvalidKeyCode(key)),
+        }
+    }
+}
+
+impl From<PianoKeyCode> for u8 {
+    fn from(key: PianoKeyCode) -> Self {
+        key.0
+    }
 }
 
 impl PianoKeyCode {
+    const KEY_NAMES: [&'static str; 12] = [
+        "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+    ];
+
     pub fn to_key_name(self) -> String {
-        (match self {
-            Self::Eb0 => "D#",
-            Self::E0 => "E",
-            Self::F0 => "F",
-            Self::Gb0 => "F#",
-            Self::G0 => "G",
-            Self::Ab0 => "G#",
-            Self::A1 => "A",
-            Self::Bb1 => "A#",
-            Self::B1 => "B",
-            Self::C1 => "C",
-            Self::Db1 => "C#",
-            Self::D1 => "D",
-            Self::Eb1 => "D#",
-            Self::E1 => "E",
-            Self::F1 => "F",
-            Self::Gb1 => "F#",
-            Self::G1 => "G",
-            Self::Ab1 => "G#",
-            Self::A2 => "A",
-            Self::Bb2 => "A#",
-            Self::B2 => "B",
-            Self::C2 => "C",
-            Self::Db2 => "C#",
-            Self::D2 => "D",
-            Self::Eb2 => "D#",
-            Self::E2 => "E",
-            Self::F2 => "F",
-            Self::Gb2 => "F#",
-            Self::G2 => "G",
-            Self::Ab2 => "G#",
-            Self::A3 => "A",
-            Self::Bb3 => "A#",
-            Self::B3 => "B",
-            Self::C3 => "C",
-            Self::Db3 => "C#",
-            Self::D3 => "D",
-            Self::Eb3 => "D#",
-            Self::E3 => "E",
-            Self::F3 => "F",
-            Self::Gb3 => "F#",
-            Self::G3 => "G",
-            Self::Ab3 => "G#",
-            Self::A4 => "A",
-            Self::Bb4 => "A#",
-            Self::B4 => "B",
-            Self::C4 => "C",
-            Self::Db4 => "C#",
-            Self::D4 => "D",
-            Self::Eb4 => "D#",
-            Self::E4 => "E",
-            Self::F4 => "F",
-            Self::Gb4 => "F#",
-            Self::G4 => "G",
-            Self::Ab4 => "G#",
-            Self::A5 => "A",
-            Self::Bb5 => "A#",
-            Self::B5 => "B",
-            Self::C5 => "C",
-            Self::Db5 => "C#",
-            Self::D5 => "D",
-            Self::Eb5 => "D#",
-            Self::E5 => "E",
-            Self::F5 => "F",
-            Self::Gb5 => "F#",
-            Self::G5 => "G",
-            Self::Ab5 => "G#",
-            Self::A6 => "A",
-            Self::Bb6 => "A#",
-            Self::B6 => "B",
-            Self::C6 => "C",
-            Self::Db6 => "C#",
-            Self::D6 => "D",
-            Self::Eb6 => "D#",
-            Self::E6 => "E",
-            Self::F6 => "F",
-            Self::Gb6 => "F#",
-            Self::G6 => "G",
-            Self::Ab6 => "G#",
-            Self::A7 => "A",
-            Self::Bb7 => "A#",
-            Self::B7 => "B",
-            Self::C7 => "C",
-            Self::Db7 => "C#",
-            Self::D7 => "D",
-            Self::Eb7 => "D#",
-            Self::E7 => "E",
-            Self::F7 => "F",
-            Self::Gb7 => "F#",
-            Self::G7 => "G",
-            Self::Ab7 => "G#",
-            Self::A8 => "A",
-            Self::Bb8 => "A#",
-            Self::B8 => "B",
-            Self::C8 => "C",
-            Self::Db8 => "C#",
-            Self::D8 => "D",
-            Self::Eb8 => "D#",
-            Self::E8 => "E",
-            Self::F8 => "F",
-        })
-        .to_string()
+        Self::KEY_NAMES[self.0 as usize % 12].to_string()
     }
 
-    fn from_u8(key: u8) -> Self {
-        match key {
-            15 => Self::Eb0,
-            16 => Self::E0,
-            17 => Self::F0,
-            18 => Self::Gb0,
-            19 => Self::G0,
-            20 => Self::Ab0,
-            21 => Self::A1,
-            22 => Self::Bb1,
-            23 => Self::B1,
-            24 => Self::C1,
-            25 => Self::Db1,
-            26 => Self::D1,
-            27 => Self::Eb1,
-            28 => Self::E1,
-            29 => Self::F1,
-            30 => Self::Gb1,
-            31 => Self::G1,
-            32 => Self::Ab1,
-            33 => Self::A2,
-            34 => Self::Bb2,
-            35 => Self::B2,
-            36 => Self::C2,
-            37 => Self::Db2,
-            38 => Self::D2,
-            39 => Self::Eb2,
-            40 => Self::E2,
-            41 => Self::F2,
-            42 => Self::Gb2,
-            43 => Self::G2,
-            44 => Self::Ab2,
-            45 => Self::A3,
-            46 => Self::Bb3,
-            47 => Self::B3,
-            48 => Self::C3,
-            49 => Self::Db3,
-            50 => Self::D3,
-            51 => Self::Eb3,
-            52 => Self::E3,
-            53 => Self::F3,
-            54 => Self::Gb3,
-            55 => Self::G3,
-            56 => Self::Ab3,
-            57 => Self::A4,
-            58 => Self::Bb4,
-            59 => Self::B4,
-            60 => Self::C4,
-            61 => Self::Db4,
-            62 => Self::D4,
-            63 => Self::Eb4,
-            64 => Self::E4,
-            65 => Self::F4,
-            66 => Self::Gb4,
-            67 => Self::G4,
-            68 => Self::Ab4,
-            69 => Self::A5,
-            70 => Self::Bb5,
-            71 => Self::B5,
-            72 => Self::C5,
-            73 => Self::Db5,
-            74 => Self::D5,
-            75 => Self::Eb5,
-            76 => Self::E5,
-            77 => Self::F5,
-            78 => Self::Gb5,
-            79 => Self::G5,
-            80 => Self::Ab5,
-            81 => Self::A6,
-            82 => Self::Bb6,
-            83 => Self::B6,
-            84 => Self::C6,
-            85 => Self::Db6,
-            86 => Self::D6,
-            87 => Self::Eb6,
-            88 => Self::E6,
-            89 => Self::F6,
-            90 => Self::Gb6,
-            91 => Self::G6,
-            92 => Self::Ab6,
-            93 => Self::A7,
-            94 => Self::Bb7,
-            95 => Self::B7,
-            96 => Self::C7,
-            97 => Self::Db7,
-            98 => Self::D7,
-            99 => Self::Eb7,
-            100 => Self::E7,
-            101 => Self::F7,
-            102 => Self::Gb7,
-            103 => Self::G7,
-            104 => Self::Ab7,
-            105 => Self::A8,
-            106 => Self::Bb8,
-            107 => Self::B8,
-            108 => Self::C8,
-            109 => Self::Db8,
-            110 => Self::D8,
-            111 => Self::Eb8,
-            112 => Self::E8,
-            113 => Self::F8,
-            k => panic!("Invalid Piano Key Code: {}", k),
-        }
+    /// Octave number in scientific pitch notation, from -1 to 9.
+    pub fn octave(self) -> i8 {
+        (self.0 / 12) as i8 - 1
+    }
+
+    /// Key name with its octave, e.g. `A0` or `C#4`.
+    pub fn to_scientific_name(self) -> String {
+        format!("{}{}", self.to_key_name(), self.octave())
+    }
+
+    /// Whether the note is one of the 88 keys of a standard piano.
+    pub fn is_on_piano(self) -> bool {
+        (21..=108).contains(&self.0)
     }
 }
 
@@ -332,26 +113,17 @@ impl Serialize for PianoKeyCode {
     where
         S: Serializer,
     {
-        // Serialize the enum as a u8
-        serializer.serialize_u8(*self as u8)
+        serializer.serialize_u8(self.0)
     }
 }
 
-// Implement Deserialize for NoteState
 impl<'de> Deserialize<'de> for PianoKeyCode {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
-        // Deserialize the value as a u8
         let value = u8::deserialize(deserializer)?;
-        match value {
-            15..=113 => Ok(PianoKeyCode::from_u8(value)),
-            _ => Err(serde::de::Error::custom(format!(
-                "Invalid NoteState value: {}",
-                value
-            ))),
-        }
+        PianoKeyCode::try_from(value).map_err(serde::de::Error::custom)
     }
 }
 
@@ -393,9 +165,30 @@ impl<'de> Deserialize<'de> for NoteState {
 #[derive(Debug, Serialize, Deserialize, Clone, Copy)]
 struct Velocity(u8);
 
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+struct Pressure(u8);
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+struct Controller(u8);
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+struct ControlValue(u8);
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+struct Program(u8);
+
+/// Raw 14-bit pitch bend value, where 8192 is the centre position.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+struct PitchBend(u16);
+
 #[derive(Debug, Serialize, Deserialize, Clone, Copy)]
 enum EventType {
     Note(NoteState, PianoKeyCode, Velocity),
+    PolyAftertouch(PianoKeyCode, Pressure),
+    ControlChange(Controller, ControlValue),
+    ProgramChange(Program),
+    ChannelPressure(Pressure),
+    PitchBend(PitchBend),
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -469,43 +262,213 @@ impl<'de> Deserialize<'de> for Channel {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 struct PianoEvent {
     event_type: EventType,
     channel: Channel,
+    /// Port id of the input the event came from.
+    source: String,
 }
 
-struct MidiMessageParser<'a> {
-    msg: &'a [u8],
+impl PianoEvent {
+    fn to_live_event(&self) -> LiveEvent<'static> {
+        let key = |key: PianoKeyCode| u7::from_int_lossy(key.into());
+        let message = match self.event_type {
+            EventType::Note(NoteState::On, k, Velocity(vel)) => MidiMessage::NoteOn {
+                key: key(k),
+                vel: u7::from_int_lossy(vel),
+            },
+            EventType::Note(NoteState::Off, k, Velocity(vel)) => MidiMessage::NoteOff {
+                key: key(k),
+                vel: u7::from_int_lossy(vel),
+            },
+            EventType::PolyAftertouch(k, Pressure(vel)) => MidiMessage::Aftertouch {
+                key: key(k),
+                vel: u7::from_int_lossy(vel),
+            },
+            EventType::ControlChange(Controller(controller), ControlValue(value)) => {
+                MidiMessage::Controller {
+                    controller: u7::from_int_lossy(controller),
+                    value: u7::from_int_lossy(value),
+                }
+            }
+            EventType::ProgramChange(Program(program)) => MidiMessage::ProgramChange {
+                program: u7::from_int_lossy(program),
+            },
+            EventType::ChannelPressure(Pressure(vel)) => MidiMessage::ChannelAftertouch {
+                vel: u7::from_int_lossy(vel),
+            },
+            EventType::PitchBend(PitchBend(bend)) => MidiMessage::PitchBend {
+                bend: midly::PitchBend(u14::from_int_lossy(bend)),
+            },
+        };
+
+        LiveEvent::Midi {
+            channel: u4::from_int_lossy(self.channel as u8),
+            message,
+        }
+    }
 }
 
-impl<'a> MidiMessageParser<'a> {
-    fn parse(&self) -> Option<PianoEvent> {
-        let channel = Channel::from_u8(self.msg[0] & 0x0F);
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ParseError {
+    /// The buffer contained no bytes at all.
+    Empty,
+    /// A data byte arrived without a preceding status byte to run on.
+    MissingStatus,
+    /// The message ended before all of its data bytes were received.
+    Truncated {
+        status: u8,
+        expected: usize,
+        found: usize,
+    },
+    /// A status byte, with its high bit set, arrived where a data byte was
+    /// expected.
+    UnexpectedStatus(u8),
+    /// A note message referred to a key outside the MIDI note range.
+    NoteOutOfRange(u8),
+    /// System common and real-time messages are not channel-voice messages.
+    Unsupported(u8),
+}
 
-        let event_type = match self.msg[0] & 0xF0 {
-            144 => EventType::Note(
-                NoteState::On,
-                PianoKeyCode::from_u8(self.msg[1]),
-                Velocity(self.msg[2]),
-            ),
-            128 => EventType::Note(
-                NoteState::Off,
-                PianoKeyCode::from_u8(self.msg[1]),
-                Velocity(self.msg[2]),
+impl From<InvalidKeyCode> for ParseError {
+    fn from(InvalidKeyCode(key): InvalidKeyCode) -> Self {
+        ParseError::NoteOutOfRange(key)
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::Empty => write!(f, "empty MIDI message"),
+            ParseError::MissingStatus => write!(f, "data byte without running status"),
+            ParseError::Truncated {
+                status,
+                expected,
+                found,
+            } => write!(
+                f,
+                "status {:#04X} expects {} data bytes, found {}",
+                status, expected, found
             ),
-            _ => return None,
+            ParseError::UnexpectedStatus(byte) => {
+                write!(f, "unexpected status {:#04X} in place of a data byte", byte)
+            }
+            ParseError::NoteOutOfRange(key) => write!(f, "note {} is out of range", key),
+            ParseError::Unsupported(status) => write!(f, "unsupported status {:#04X}", status),
+        }
+    }
+}
+
+/// Decodes channel-voice messages from raw MIDI bytes.
+///
+/// The parser remembers the last channel-voice status byte so that messages
+/// sent with running status (data bytes only) are decoded correctly.
+#[derive(Debug)]
+struct MidiMessageParser {
+    /// Port id of the input, with which every event is tagged.
+    source: String,
+    running_status: Option<u8>,
+}
+
+impl MidiMessageParser {
+    fn new(source: String) -> Self {
+        MidiMessageParser {
+            source,
+            running_status: None,
+        }
+    }
+
+    fn parse(&mut self, msg: &[u8]) -> Result<PianoEvent, ParseError> {
+        let (&first, rest) = msg.split_first().ok_or(ParseError::Empty)?;
+
+        let (status, data) = if first & 0x80 != 0 {
+            match first {
+                // System real-time messages may be interleaved anywhere and do
+                // not affect running status.
+                0xF8..=0xFF => return Err(ParseError::Unsupported(first)),
+                // System common messages cancel running status.
+                0xF0..=0xF7 => {
+                    self.running_status = None;
+                    return Err(ParseError::Unsupported(first));
+                }
+                _ => {
+                    self.running_status = Some(first);
+                    (first, rest)
+                }
+            }
+        } else {
+            (self.running_status.ok_or(ParseError::MissingStatus)?, msg)
+        };
+
+        let expected = match status & 0xF0 {
+            0xC0 | 0xD0 => 1,
+            _ => 2,
+        };
+        if data.len() < expected {
+            return Err(ParseError::Truncated {
+                status,
+                expected,
+                found: data.len(),
+            });
+        }
+        let value = |byte: u8| match byte {
+            0..=127 => Ok(byte),
+            _ => Err(ParseError::UnexpectedStatus(byte)),
+        };
+        let key = |byte: u8| -> Result<PianoKeyCode, ParseError> {
+            Ok(PianoKeyCode::try_from(value(byte)?)?)
+        };
+
+        let channel = Channel::from_u8(status & 0x0F);
+
+        let event_type = match status & 0xF0 {
+            0x90 => EventType::Note(NoteState::On, key(data[0])?, Velocity(value(data[1])?)),
+            0x80 => EventType::Note(NoteState::Off, key(data[0])?, Velocity(value(data[1])?)),
+            0xA0 => EventType::PolyAftertouch(key(data[0])?, Pressure(value(data[1])?)),
+            0xB0 => {
+                EventType::ControlChange(Controller(value(data[0])?), ControlValue(value(data[1])?))
+            }
+            0xC0 => EventType::ProgramChange(Program(value(data[0])?)),
+            0xD0 => EventType::ChannelPressure(Pressure(value(data[0])?)),
+            0xE0 => EventType::PitchBend(PitchBend(
+                (value(data[0])? as u16) | ((value(data[1])? as u16) << 7),
+            )),
+            _ => unreachable!("status byte has its high bit set"),
         };
 
         let piano_event = PianoEvent {
-            event_type,
+            event_type: normalize_event_type(event_type),
             channel,
+            source: self.source.clone(),
         };
 
-        Some(piano_event)
+        Ok(piano_event)
     }
 }
 
+/// A failure outside of any command, or one a command cannot return because
+/// it already succeeded otherwise. Emitted to the frontend as an `app_error`
+/// event.
+#[derive(Debug, Serialize, Clone)]
+#[serde(tag = "kind", content = "message")]
+enum AppErrorEvent {
+    /// A live input sent bytes that are not a channel-voice message.
+    InvalidMidiMessage(String),
+    MidiOutConnect(String),
+    MidiOutSend(String),
+    /// A file was saved, but could not be added to the recordings library.
+    LibraryIndex(String),
+    /// The audio output that was replaced failed while playing, or its file
+    /// could not be finished.
+    AudioOutClose(String),
+}
+
+fn emit_error(app_handle: &AppHandle, error: AppErrorEvent) {
+    app_handle
+        .emit("app_error", error)
+        .expect("error while emitting app error");
+}
 
 enum MidiOutState {
     Connected(Option<(MidiOutputConnection, AvailableMidiOutput)>),
@@ -513,123 +476,260 @@ enum MidiOutState {
 }
 
 impl MidiOutState {
-    fn close(&mut self) -> bool {
-        if let MidiOutState::Connected(midi_out_conn) = self {
-            let (midi_out) = midi_out_conn.take().unwrap().0.close();
-            *self = MidiOutState::Disconnected(Some(midi_out));
-            return true;
-        }
-        false
+    /// Takes the connection to the output out of the state, to be closed by
+    /// `close_midi_output` once the state is unlocked. A new client is left in
+    /// its place, as the old one only comes back once the connection is closed.
+    fn take(&mut self) -> Option<MidiOutputConnection> {
+        let MidiOutState::Connected(midi_out_conn) = self else {
+            return None;
+        };
+        let midi_out_conn = midi_out_conn.take().unwrap().0;
+        *self = MidiOutState::Disconnected(Some(
+            MidiOutput::new("midir output").expect("error while creating midi output"),
+        ));
+        Some(midi_out_conn)
     }
 
     fn connect_with_id(&mut self, id: String, app_handle: AppHandle) -> bool {
         if let MidiOutState::Disconnected(midi_out) = self {
             let midi_out = midi_out.take().unwrap();
-            let port = midi_out.find_port_by_id(id).unwrap();
+            let Some(port) = midi_out.find_port_by_id(id) else {
+                *self = MidiOutState::Disconnected(Some(midi_out));
+                return false;
+            };
             let available_midi_output = AvailableMidiOutput {
-                name: midi_out.port_name(&port).unwrap(),
+                name: midi_out.port_name(&port).unwrap_or_default(),
                 index: port.id(),
             };
 
-            let midi_out_conn = midi_out
-                    .connect(
-                        &port,
-                        "midir-write-output",
-                    )
-                    .expect("error while connecting to midi output");
-
-            *self = MidiOutState::Connected(Some((midi_out_conn, available_midi_output)));
-            return true;
+            match midi_out.connect(&port, "midir-write-output") {
+                Ok(midi_out_conn) => {
+                    *self = MidiOutState::Connected(Some((midi_out_conn, available_midi_output)));
+                    return true;
+                }
+                Err(err) => {
+                    emit_error(&app_handle, AppErrorEvent::MidiOutConnect(err.to_string()));
+                    *self = MidiOutState::Disconnected(Some(err.into_inner()));
+                }
+            }
         }
         false
     }
 
-    fn send_out(&mut self, data: &[u8]) {
-        if let MidiOutState::Connected(midi_out_conn) = self {
-            let midi_out_conn = midi_out_conn.as_mut().unwrap();
-            midi_out_conn.0.send(data).expect("error while sending midi data");
+    /// Sends `data` if an output is connected. Returns whether it was sent.
+    ///
+    /// The input callbacks and the metronome send while holding the state, so
+    /// an output that fails, e.g. because it was unplugged, is only reported.
+    fn send_out(&mut self, data: &[u8], app_handle: &AppHandle) -> bool {
+        let MidiOutState::Connected(midi_out_conn) = self else {
+            return false;
+        };
+        let midi_out_conn = midi_out_conn.as_mut().unwrap();
+        match midi_out_conn.0.send(data) {
+            Ok(()) => true,
+            Err(err) => {
+                emit_error(app_handle, AppErrorEvent::MidiOutSend(err.to_string()));
+                false
+            }
         }
     }
 }
 
-enum MidiInState {
-    Connected(Option<(JoinHandle<MidiInputConnection<()>>, AvailableMidiInput)>),
-    Disconnected(Option<MidiInput>),
+/// Open connections to MIDI inputs. Any number of inputs may be connected
+/// at once; their events are told apart by the `source` of each
+/// `PianoEvent`.
+struct MidiInState {
+    /// Client used to list the ports. Connecting consumes a client, so every
+    /// connection gets one of its own.
+    midi_in: MidiInput,
+    connections: Vec<(JoinHandle<MidiInputConnection<()>>, AvailableMidiInput)>,
 }
 
 impl MidiInState {
-    fn close(&mut self) -> bool {
-        if let MidiInState::Connected(midi_in_conn) = self {
-            let (midi_in, _) = midi_in_conn.take().unwrap().0.join().unwrap().close();
-            *self = MidiInState::Disconnected(Some(midi_in));
-            return true;
-        }
-        false
+    fn is_connected(&self, id: &str) -> bool {
+        self.connections.iter().any(|(_, input)| input.index == id)
     }
 
-    fn connect_with_id(&mut self, id: String, app_handle: AppHandle) -> bool {
-        if let MidiInState::Disconnected(midi_in) = self {
-            let midi_in = midi_in.take().unwrap();
-            let port = midi_in.find_port_by_id(id).unwrap();
-            let available_midi_input = AvailableMidiInput {
-                name: midi_in.port_name(&port).unwrap(),
-                index: port.id(),
-            };
+    /// Takes the connection to the input with port id `id` out of the state,
+    /// to be closed by `close_midi_inputs` once the state is unlocked.
+    fn remove(&mut self, id: &str) -> Option<JoinHandle<MidiInputConnection<()>>> {
+        let position = self
+            .connections
+            .iter()
+            .position(|(_, input)| input.index == id)?;
+        Some(self.connections.remove(position).0)
+    }
 
-            let join_handle = thread::spawn(move || {
-                let midi_in_conn = midi_in
-                    .connect(
-                        &port,
-                        "midir-read-input",
-                        move |_timestamp, message, _| {
-                            let parser = MidiMessageParser { msg: message };
-                            let piano_event = parser.parse();
-                            if let Some(piano_event) = piano_event {
-                                app_handle
-                                    .emit("piano_event", piano_event)
-                                    .expect("error while emitting piano event");
-                            }
+    fn remove_all(&mut self) -> Vec<JoinHandle<MidiInputConnection<()>>> {
+        self.connections
+            .drain(..)
+            .map(|(join_handle, _)| join_handle)
+            .collect()
+    }
 
-                            let state: State<'_, Mutex<AppState>> = app_handle.state();
-                            let mut state = state.lock().unwrap();
+    fn connect_with_id(&mut self, id: String, app_handle: AppHandle) -> bool {
+        if self.is_connected(&id) {
+            return false;
+        }
+        let midi_in = MidiInput::new("midir input").expect("error while creating midi input");
+        let Some(port) = midi_in.find_port_by_id(id) else {
+            return false;
+        };
+        let available_midi_input = AvailableMidiInput {
+            name: midi_in.port_name(&port).unwrap_or_default(),
+            index: port.id(),
+        };
 
-                            if let Some((recording, timestart, last_event_time)) =
-                                &mut state.recording
+        let input = available_midi_input.clone();
+        let join_handle = thread::spawn(move || {
+            let mut parser = MidiMessageParser::new(input.index.clone());
+            let midi_in_conn = midi_in
+                .connect(
+                    &port,
+                    "midir-read-input",
+                    move |_timestamp, message, _| {
+                        let piano_event = match parser.parse(message) {
+                            Ok(piano_event) => piano_event,
+                            Err(ParseError::Unsupported(_)) => return,
+                            Err(err) => {
+                                emit_error(
+                                    &app_handle,
+                                    AppErrorEvent::InvalidMidiMessage(err.to_string()),
+                                );
+                                return;
+                            }
+                        };
+
+                        app_handle
+                            .emit("piano_event", &piano_event)
+                            .expect("error while emitting piano event");
+
+                        let state: State<'_, Mutex<AppState>> = app_handle.state();
+                        let mut state = state.lock().unwrap();
+
+                        for output in state.pedal_state.handle(&piano_event) {
+                            match output {
+                                PedalOutput::Pedal(pedal_event) => app_handle
+                                    .emit("pedal_event", pedal_event)
+                                    .expect("error while emitting pedal event"),
+                                PedalOutput::NoteReleased(released) => app_handle
+                                    .emit("note_released", released)
+                                    .expect("error while emitting note released event"),
+                            }
+                        }
+
+                        if let Some(message) = state.thru.route(piano_event.to_live_event()) {
+                            state.midi_out_state.send_out(&message, &app_handle);
+                        }
+
+                        if let Some(audio_out) = &state.audio_out {
+                            let mut message = Vec::with_capacity(3);
+                            piano_event
+                                .to_live_event()
+                                .write(&mut message)
+                                .expect("Error while writing MIDI event");
+                            audio_out.send(&message);
+                        }
+
+                        if let Some(recorder) = &mut state.recording {
+                            if let LiveEvent::Midi { channel, message } =
+                                piano_event.to_live_event()
+                            {
+                                recorder.record(&input, channel, message);
+                            }
+                        }
+
+                        if let (Some(playback), EventType::Note(note_state, key, _)) =
+                            (&state.playback, piano_event.event_type)
+                        {
+                            playback.note_input(key.into(), matches!(note_state, NoteState::On));
+                        }
+
+                        if let EventType::Note(NoteState::On, key, Velocity(velocity)) =
+                            piano_event.event_type
+                        {
+                            let position = state.playback.as_ref().map(|playback| {
+                                (playback.pass_position(), playback.settings().transpose)
+                            });
+                            if let (Some(scoring), Some(((pass, time), transpose))) =
+                                (&mut state.scoring, position)
                             {
-                                if let LiveEvent::Midi { channel, message } =
-                                    LiveEvent::parse(message).unwrap()
-                                {
-                                 
-                                    let now = timestart.elapsed().as_millis() as u32;
-                                    let delta = now - *last_event_time; // Delta since the last event
-                                    *last_event_time = now;
-                                    let track_event = TrackEvent {
-                                        delta: u28::from_int_lossy(delta),
-                                        kind: TrackEventKind::Midi { channel, message },
-                                    };
-                                    recording.push(track_event);
-                                } else {
-                                    println!("Invalid MIDI message");
-                                }
+                                scoring.note_on(
+                                    pass,
+                                    time,
+                                    piano_event.channel as u8,
+                                    key.into(),
+                                    velocity,
+                                    transpose,
+                                );
                             }
-                        },
-                        (),
-                    )
-                    .expect("error while connecting to midi input");
+                        }
+                    },
+                    (),
+                )
+                .expect("error while connecting to midi input");
 
-                midi_in_conn
-            });
+            midi_in_conn
+        });
 
-            *self = MidiInState::Connected(Some((join_handle, available_midi_input)));
-            return true;
+        self.connections.push((join_handle, available_midi_input));
+        true
+    }
+}
+
+fn close_midi_output(mut midi_out_conn: MidiOutputConnection) {
+    // All Notes Off, so nothing keeps sounding on the device.
+    for channel in 0..16 {
+        midi_out_conn.send(&[0xB0 | channel, 123, 0]).ok();
+    }
+    midi_out_conn.close();
+}
+
+/// Closes input connections taken out of the state. Returns whether there
+/// was any.
+///
+/// Closing waits for a running input callback, which locks the state, so the
+/// state must not be locked meanwhile.
+fn close_midi_inputs(
+    connections: impl IntoIterator<Item = JoinHandle<MidiInputConnection<()>>>,
+) -> bool {
+    let mut closed = false;
+    for join_handle in connections {
+        // A connect thread that panicked left no connection to close.
+        if let Ok(midi_in_conn) = join_handle.join() {
+            midi_in_conn.close();
         }
-        false
+        closed = true;
     }
+    closed
 }
-struct AppState<'a> {
+
+struct AppState {
     midi_in_state: MidiInState,
     midi_out_state: MidiOutState,
-    recording: Option<(Track<'a>, Instant, u32)>,
+    /// Pedals and sounding notes of the live input. Shared by every input,
+    /// so a pedal unit holds the notes played on a separate keyboard.
+    pedal_state: PedalState,
+    recording: Option<Recorder>,
+    playback: Option<PlaybackEngine>,
+    /// The file being played back, kept for overdubbing.
+    playback_file: Option<Smf<'static>>,
+    playback_settings: PlaybackSettings,
+    wait_mode: Option<WaitModeSettings>,
+    scoring: Option<ScoringSession>,
+    metronome: Option<Metronome>,
+    /// Count-in of the current recording.
+    count_in: Option<Metronome>,
+    thru: ThruSettings,
+    metronome_settings: MetronomeSettings,
+    /// Instrument of the internal synth.
+    instrument: Instrument,
+    /// The internal synth playing live, next to the MIDI output.
+    audio_out: Option<AudioOut>,
+    /// Names of the devices last connected by the user, reconnected when
+    /// they are plugged in again.
+    last_midi_ins: Vec<String>,
+    last_midi_out: Option<String>,
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -638,14 +738,35 @@ pub fn run() {
         .plugin(tauri_plugin_dialog::init())
         .setup(|app| {
             app.manage(Mutex::new(AppState {
-                midi_in_state: MidiInState::Disconnected(Some(
-                    MidiInput::new("midir input").expect("error while creating midi input"),
-                )),
+                midi_in_state: MidiInState {
+                    midi_in: MidiInput::new("midir input")
+                        .expect("error while creating midi input"),
+                    connections: vec![],
+                },
                 midi_out_state: MidiOutState::Disconnected(Some(
                     MidiOutput::new("midir output").expect("error while creating midi output"),
                 )),
+                pedal_state: PedalState::default(),
                 recording: None,
+                playback: None,
+                playback_file: None,
+                playback_settings: PlaybackSettings::default(),
+                wait_mode: None,
+                scoring: None,
+                metronome: None,
+                count_in: None,
+                thru: ThruSettings::default(),
+                metronome_settings: MetronomeSettings::default(),
+                instrument: Instrument::default(),
+                audio_out: None,
+                last_midi_ins: vec![],
+                last_midi_out: None,
             }));
+
+            let app_handle = app.app_handle().clone();
+            devices::watch(app_handle.clone(), move |change| {
+                handle_device_change(&app_handle, change)
+            });
             Ok(())
         })
         .plugin(tauri_plugin_shell::init())
@@ -653,12 +774,52 @@ pub fn run() {
             greet,
             get_available_midi_inputs,
             get_midi_in_connection_info,
+            get_midi_in_connections,
             connect_to_midi_in,
             disconnect_from_midi_in,
+            get_available_midi_outputs,
+            get_midi_out_connection_info,
+            connect_to_midi_out,
+            disconnect_from_midi_out,
+            get_audio_output_devices,
+            get_audio_out_info,
+            connect_to_audio_out,
+            disconnect_from_audio_out,
             start_recording,
             stop_recording,
             is_recording,playback_midi_file,
-            playback_midi_event
+            playback_midi_event,
+            get_midi_file_tracks,
+            pause_playback,
+            resume_playback,
+            seek_playback,
+            stop_playback,
+            get_playback_state,
+            set_playback_speed,
+            set_playback_transpose,
+            get_playback_settings,
+            set_playback_loop,
+            clear_playback_loop,
+            set_wait_mode,
+            start_scoring,
+            stop_scoring,
+            score_recording,
+            list_recordings,
+            update_recording_metadata,
+            rename_recording,
+            delete_recording,
+            start_metronome,
+            stop_metronome,
+            set_metronome_settings,
+            get_metronome_settings,
+            quantize_recording,
+            render_midi_to_wav,
+            load_soundfont,
+            unload_soundfont,
+            set_soundfont_preset,
+            get_soundfont_preset,
+            set_midi_thru,
+            get_midi_thru
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -682,55 +843,241 @@ struct AvailableMidiOutput {
     index: String,
 }
 
+/// Inputs that are not connected yet.
 #[tauri::command]
 fn get_available_midi_inputs(state: State<'_, Mutex<AppState>>) -> Vec<AvailableMidiInput> {
     let state = state.lock().unwrap();
-    let available_midi_inputs = match &state.midi_in_state {
-        MidiInState::Connected(_) => {
-            return vec![];
+    let midi_in_state = &state.midi_in_state;
+    midi_in_state
+        .midi_in
+        .ports()
+        .iter()
+        .filter(|port| !midi_in_state.is_connected(&port.id()))
+        .map(|port| AvailableMidiInput {
+            name: midi_in_state.midi_in.port_name(port).unwrap_or_default(),
+            index: port.id(),
+        })
+        .collect()
+}
+
+/// The first connected input; see `get_midi_in_connections` for all of them.
+#[tauri::command]
+fn get_midi_in_connection_info(app: AppHandle) -> Option<AvailableMidiInput> {
+    let state: State<'_, Mutex<AppState>> = app.state();
+    let state = state.lock().unwrap();
+    state
+        .midi_in_state
+        .connections
+        .first()
+        .map(|(_, input)| input.clone())
+}
+
+#[tauri::command]
+fn get_midi_in_connections(app: AppHandle) -> Vec<AvailableMidiInput> {
+    let state: State<'_, Mutex<AppState>> = app.state();
+    let state = state.lock().unwrap();
+    state
+        .midi_in_state
+        .connections
+        .iter()
+        .map(|(_, input)| input.clone())
+        .collect()
+}
+
+/// Connects to another input, keeping the inputs already connected.
+#[tauri::command]
+fn connect_to_midi_in(app: AppHandle, index: String) -> bool {
+    let app_handle = app.clone();
+    let state: State<'_, Mutex<AppState>> = app.state();
+    let mut state = state.lock().unwrap();
+    if !state.midi_in_state.connect_with_id(index, app_handle) {
+        return false;
+    }
+    let (_, input) = state.midi_in_state.connections.last().unwrap();
+    let name = input.name.clone();
+    if !state.last_midi_ins.contains(&name) {
+        state.last_midi_ins.push(name);
+    }
+    true
+}
+
+/// Disconnects from the input with port id `index`, or from every input.
+#[tauri::command]
+fn disconnect_from_midi_in(app: AppHandle, index: Option<String>) -> bool {
+    let state: State<'_, Mutex<AppState>> = app.state();
+    let mut state = state.lock().unwrap();
+    let connections = match index {
+        Some(index) => {
+            let name = state
+                .midi_in_state
+                .connections
+                .iter()
+                .find(|(_, input)| input.index == index)
+                .map(|(_, input)| input.name.clone());
+            state
+                .last_midi_ins
+                .retain(|last| Some(last) != name.as_ref());
+            state.midi_in_state.remove(&index).into_iter().collect()
         }
-        MidiInState::Disconnected(midi_in) => {
-            let midi_in = midi_in.as_ref().unwrap();
-            midi_in
+        None => {
+            state.last_midi_ins.clear();
+            state.midi_in_state.remove_all()
+        }
+    };
+
+    drop(state);
+    close_midi_inputs(connections)
+}
+
+#[tauri::command]
+fn get_available_midi_outputs(state: State<'_, Mutex<AppState>>) -> Vec<AvailableMidiOutput> {
+    let state = state.lock().unwrap();
+    match &state.midi_out_state {
+        MidiOutState::Connected(_) => vec![],
+        MidiOutState::Disconnected(midi_out) => {
+            let midi_out = midi_out.as_ref().unwrap();
+            midi_out
                 .ports()
                 .iter()
-                .map(|port| {
-                    let port_name = midi_in.port_name(port).unwrap();
-                    AvailableMidiInput {
-                        name: port_name,
-                        index: port.id(),
-                    }
+                .map(|port| AvailableMidiOutput {
+                    name: midi_out.port_name(port).unwrap_or_default(),
+                    index: port.id(),
                 })
-                .collect::<Vec<_>>()
+                .collect()
         }
-    };
-
-    available_midi_inputs
+    }
 }
 
 #[tauri::command]
-fn get_midi_in_connection_info(app: AppHandle) -> Option<AvailableMidiInput> {
+fn get_midi_out_connection_info(app: AppHandle) -> Option<AvailableMidiOutput> {
     let state: State<'_, Mutex<AppState>> = app.state();
     let state = state.lock().unwrap();
-    match &state.midi_in_state {
-        MidiInState::Connected(conn) => Some(conn.as_ref().unwrap().1.clone()),
-        MidiInState::Disconnected(_) => None,
+    match &state.midi_out_state {
+        MidiOutState::Connected(conn) => Some(conn.as_ref().unwrap().1.clone()),
+        MidiOutState::Disconnected(_) => None,
     }
 }
 
 #[tauri::command]
-fn connect_to_midi_in(app: AppHandle, index: String) -> bool {
+fn connect_to_midi_out(app: AppHandle, index: String) -> bool {
     let app_handle = app.clone();
     let state: State<'_, Mutex<AppState>> = app.state();
     let mut state = state.lock().unwrap();
-    state.midi_in_state.connect_with_id(index, app_handle)
+    let connected = state.midi_out_state.connect_with_id(index, app_handle);
+    if let MidiOutState::Connected(conn) = &state.midi_out_state {
+        state.last_midi_out = Some(conn.as_ref().unwrap().1.name.clone());
+    }
+    connected
+}
+
+#[tauri::command]
+fn disconnect_from_midi_out(app: AppHandle) -> bool {
+    let state: State<'_, Mutex<AppState>> = app.state();
+    let mut state = state.lock().unwrap();
+    state.last_midi_out = None;
+    let Some(midi_out_conn) = state.midi_out_state.take() else {
+        return false;
+    };
+
+    drop(state);
+    close_midi_output(midi_out_conn);
+    true
+}
+
+#[tauri::command]
+fn get_audio_output_devices() -> Result<Vec<String>, AudioError> {
+    audio::output_devices()
+}
+
+#[tauri::command]
+fn get_audio_out_info(app: AppHandle) -> Option<AudioSinkSettings> {
+    let state: State<'_, Mutex<AppState>> = app.state();
+    let state = state.lock().unwrap();
+    state
+        .audio_out
+        .as_ref()
+        .map(|audio_out| audio_out.settings().clone())
+}
+
+/// Plays live input and playback through the internal synth to `sink`, the
+/// default audio device unless given, replacing the current audio output.
+#[tauri::command]
+fn connect_to_audio_out(
+    app: AppHandle,
+    sink: Option<AudioSinkSettings>,
+    settings: Option<SynthSettings>,
+) -> Result<(), AudioError> {
+    let state: State<'_, Mutex<AppState>> = app.state();
+    let mut state = state.lock().unwrap();
+    // The new output may be the same device or file, so the old one is
+    // closed first, and a failure of it does not keep the new one from
+    // opening.
+    if let Some(Err(err)) = state.audio_out.take().map(AudioOut::close) {
+        emit_error(&app, AppErrorEvent::AudioOutClose(err.to_string()));
+    }
+
+    let audio_out = AudioOut::open(
+        sink.unwrap_or_default(),
+        &settings.unwrap_or_default(),
+        state.instrument.clone(),
+    )?;
+    state.audio_out = Some(audio_out);
+    Ok(())
 }
 
+/// Stops the internal synth, finishing the file of a file sink. Returns
+/// whether it was playing.
 #[tauri::command]
-fn disconnect_from_midi_in(app: AppHandle) -> bool {
+fn disconnect_from_audio_out(app: AppHandle) -> Result<bool, AudioError> {
     let state: State<'_, Mutex<AppState>> = app.state();
     let mut state = state.lock().unwrap();
-    state.midi_in_state.close()
+    match state.audio_out.take() {
+        Some(audio_out) => audio_out.close().map(|_| true),
+        None => Ok(false),
+    }
+}
+
+/// Drops the connection to a device that was unplugged, and reconnects to
+/// the last devices the user chose when they are plugged in again.
+fn handle_device_change(app: &AppHandle, change: &DeviceChange) {
+    let state: State<'_, Mutex<AppState>> = app.state();
+    let mut state = state.lock().unwrap();
+
+    match change {
+        DeviceChange::Removed(device) => match device.kind {
+            DeviceKind::Input => {
+                let connection = state.midi_in_state.remove(&device.index);
+                drop(state);
+                close_midi_inputs(connection);
+            }
+            DeviceKind::Output => {
+                if let MidiOutState::Connected(Some((_, output))) = &state.midi_out_state {
+                    if output.index == device.index {
+                        let midi_out_conn = state.midi_out_state.take();
+                        drop(state);
+                        close_midi_output(midi_out_conn.unwrap());
+                    }
+                }
+            }
+        },
+        DeviceChange::Added(device) => match device.kind {
+            DeviceKind::Input => {
+                if state.last_midi_ins.contains(&device.name) {
+                    state
+                        .midi_in_state
+                        .connect_with_id(device.index.clone(), app.clone());
+                }
+            }
+            DeviceKind::Output => {
+                let is_last = state.last_midi_out.as_ref() == Some(&device.name);
+                if is_last && matches!(state.midi_out_state, MidiOutState::Disconnected(_)) {
+                    state
+                        .midi_out_state
+                        .connect_with_id(device.index.clone(), app.clone());
+                }
+            }
+        },
+    }
 }
 
 #[tauri::command]
@@ -740,217 +1087,607 @@ fn is_recording(app: AppHandle) -> bool {
     state.recording.is_some()
 }
 
+/// Starts recording the live input. With `overdub`, the recording follows the
+/// file being played back and is saved together with its tracks; `settings`
+/// and `count_in` are then ignored, as the file's own tempo is used.
+/// Otherwise `count_in` counts in at the tempo of `settings`, and the
+/// recording starts when it ends. While the metronome is running, the
+/// count-in and the recording follow its tempo and time signature instead,
+/// and the count-in starts on its next downbeat.
 #[tauri::command]
-fn start_recording(app: AppHandle) -> bool {
+fn start_recording(
+    app: AppHandle,
+    settings: Option<RecordingSettings>,
+    overdub: Option<bool>,
+    count_in: Option<CountInSettings>,
+) -> bool {
+    let mut settings = settings.unwrap_or_default();
+    if !settings.is_valid() {
+        return false;
+    }
+
     let state: State<'_, Mutex<AppState>> = app.state();
     let mut state = state.lock().unwrap();
     if state.recording.is_some() {
         return false;
     }
-    let recording = Track::default();
-    state.recording = Some((recording, Instant::now(), 0));
+
+    let recorder = if overdub.unwrap_or(false) {
+        let (Some(playback), Some(smf)) = (&state.playback, &state.playback_file) else {
+            return false;
+        };
+        Recorder::overdub(smf.clone(), playback.clock(), &settings)
+    } else if let Some(count_in) = count_in.filter(|count_in| count_in.bars > 0) {
+        // A running metronome already keeps time, so the count-in only counts
+        // visually, in step with its clicks.
+        let start = match &state.metronome {
+            Some(metronome) => {
+                settings.bpm = metronome.settings().bpm;
+                settings.time_signature = metronome.settings().time_signature;
+                if !settings.is_valid() {
+                    return false;
+                }
+                metronome.next_downbeat(Instant::now())
+            }
+            None => Instant::now(),
+        };
+        let metronome_settings = MetronomeSettings {
+            bpm: settings.bpm,
+            time_signature: settings.time_signature,
+            ..state.metronome_settings.clone()
+        };
+        let audible = count_in.audible && state.metronome.is_none();
+        let click_app = app.clone();
+        let (metronome, start) = Metronome::count_in(
+            app.clone(),
+            metronome_settings,
+            count_in.bars,
+            start,
+            move |message| {
+                // Count-in clicks come before the take, so they are never
+                // recorded.
+                if audible {
+                    let state: State<'_, Mutex<AppState>> = click_app.state();
+                    let mut state = state.lock().unwrap();
+                    state.midi_out_state.send_out(&message, &click_app);
+                }
+            },
+        );
+        state.count_in = Some(metronome);
+        Recorder::new(settings, start)
+    } else {
+        Recorder::new(settings, Instant::now())
+    };
+    state.recording = Some(recorder);
     true
 }
 
+/// Stops recording and saves the take to `path`, or to a new timestamped
+/// file in the recordings directory. Returns the path it was saved to.
 #[tauri::command]
-fn stop_recording(app: AppHandle) -> bool {
+fn stop_recording(app: AppHandle, path: Option<String>) -> Result<String, SaveRecordingError> {
     let state: State<'_, Mutex<AppState>> = app.state();
+    // The count-in thread locks the state to send its clicks.
+    let count_in = state.lock().unwrap().count_in.take();
+    if let Some(count_in) = count_in {
+        count_in.stop();
+    }
+
     let mut state = state.lock().unwrap();
-    let mut success = false;
-    if let Some((recording, timestart, last_time_event)) = &mut state.recording {
-        let delta = timestart.elapsed().as_millis() as u32 - *last_time_event;
-        recording.push(TrackEvent {
-            delta: u28::from_int_lossy(delta),
-            kind: TrackEventKind::Meta(midly::MetaMessage::EndOfTrack),
-        });
+    if state.recording.is_none() {
+        return Err(SaveRecordingError::NotRecording);
+    }
 
-        let mut smf = Smf::new(Header {
-            format: Format::SingleTrack,
-            timing: Timing::Metrical(u15::from_int_lossy(480)),
-        });
+    let path = match path {
+        Some(path) => PathBuf::from(path),
+        None => default_recording_path(&recordings_dir(&app)?),
+    };
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
 
-        smf.tracks.push(mem::replace(recording, Track::default()));
+    // The recording goes on until the take is saved, so it can be saved again
+    // to another path if this one fails.
+    let smf = state.recording.as_ref().unwrap().finish();
+    smf.save(&path)?;
+
+    // The take is saved either way, so a broken index is only reported.
+    let indexed = recordings_dir(&app)
+        .and_then(Library::open)
+        .and_then(|mut library| library.add(path.clone(), &smf));
+    if let Err(err) = indexed {
+        emit_error(&app, AppErrorEvent::LibraryIndex(err.to_string()));
+    }
+    state.recording = None;
 
-        smf.save("recording.mid")
-            .expect("error while saving midi file");
+    Ok(path.to_string_lossy().into_owned())
+}
 
-        success = true;
+fn recordings_dir(app: &AppHandle) -> Result<PathBuf, LibraryError> {
+    app.path()
+        .app_data_dir()
+        .map(|dir| dir.join("recordings"))
+        .map_err(|err| LibraryError::NoDataDirectory(err.to_string()))
+}
+
+/// Saves a quantized copy of the MIDI file at `path` to `output_path`, or
+/// next to the original. Returns the path of the copy.
+#[tauri::command]
+fn quantize_recording(
+    app: AppHandle,
+    path: String,
+    settings: QuantizeSettings,
+    output_path: Option<String>,
+) -> Result<String, QuantizeError> {
+    let path = PathBuf::from(path);
+    let data = std::fs::read(&path)?;
+    let smf = Smf::parse(&data).map_err(|err| QuantizeError::Io(err.to_string()))?;
+    let quantized = quantize(&smf, &settings)?;
+
+    let output_path = output_path.map_or_else(|| quantized_path(&path), PathBuf::from);
+    quantized.save(&output_path)?;
+
+    let indexed = recordings_dir(&app)
+        .and_then(Library::open)
+        .and_then(|mut library| library.add(output_path.clone(), &quantized));
+    if let Err(err) = indexed {
+        emit_error(&app, AppErrorEvent::LibraryIndex(err.to_string()));
     }
 
-    state.recording = None;
+    Ok(output_path.to_string_lossy().into_owned())
+}
 
-    return success;
+/// Renders the MIDI file at `path` with the internal synth and its current
+/// instrument, and saves it as a WAV file to `output_path`, or next to the
+/// original. Returns the path of the WAV file.
+#[tauri::command]
+async fn render_midi_to_wav(
+    app: AppHandle,
+    path: String,
+    settings: Option<SynthSettings>,
+    output_path: Option<String>,
+) -> Result<String, RenderError> {
+    let settings = settings.unwrap_or_default();
+    if !settings.is_valid() {
+        return Err(RenderError::InvalidSettings);
+    }
+
+    let path = PathBuf::from(path);
+    let instrument = {
+        let state: State<'_, Mutex<AppState>> = app.state();
+        let state = state.lock().unwrap();
+        state.instrument.clone()
+    };
+    let output_path = output_path.map_or_else(|| rendered_path(&path), PathBuf::from);
+
+    // Rendering takes a while, so it runs where it cannot hold up the
+    // async runtime, streaming to the file as it goes.
+    let render_path = output_path.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        let data = std::fs::read(&path)?;
+        let smf = Smf::parse(&data).map_err(|err| RenderError::Io(err.to_string()))?;
+        let mut writer = WavWriter::create(&render_path, settings.sample_rate, 2)?;
+        render_smf(&smf, &settings, instrument, |samples| writer.write(samples))?;
+        writer.finish()?;
+        Ok::<_, RenderError>(())
+    })
+    .await
+    .map_err(|err| RenderError::Io(err.to_string()))??;
+
+    Ok(output_path.to_string_lossy().into_owned())
 }
 
-struct PlaybackEvent {
-    delta: u32,
-    message: [u8; 3],
-    time_length: u32,
-    is_note_on: bool,
+/// Loads the SoundFont at `path` as the synth's instrument, starting with its
+/// first preset. Returns every preset of the file.
+#[tauri::command]
+async fn load_soundfont(app: AppHandle, path: String) -> Result<Vec<PresetInfo>, SoundFontError> {
+    let font = SoundFont::load(&PathBuf::from(path))?;
+    let presets = font.presets();
+
+    let state: State<'_, Mutex<AppState>> = app.state();
+    let mut state = state.lock().unwrap();
+    state.instrument = Instrument::SoundFont {
+        preset: font.default_preset(),
+        font: Arc::new(font),
+    };
+    update_audio_out_instrument(&state);
+    Ok(presets)
 }
 
-impl PlaybackEvent {
-    fn to_packed(&self) -> PackedPlaybackEvent {
-        PackedPlaybackEvent {
-            is_note_on: self.is_note_on,
-            message: self.message,
-            time_length: self.time_length,
-        }
+/// Goes back to the built-in piano.
+#[tauri::command]
+fn unload_soundfont(app: AppHandle) -> bool {
+    let state: State<'_, Mutex<AppState>> = app.state();
+    let mut state = state.lock().unwrap();
+    let was_loaded = matches!(state.instrument, Instrument::SoundFont { .. });
+    state.instrument = Instrument::Piano;
+    update_audio_out_instrument(&state);
+    was_loaded
+}
+
+#[tauri::command]
+fn set_soundfont_preset(app: AppHandle, bank: u16, program: u16) -> Result<(), SoundFontError> {
+    let state: State<'_, Mutex<AppState>> = app.state();
+    let mut state = state.lock().unwrap();
+    let Instrument::SoundFont { font, preset } = &mut state.instrument else {
+        return Err(SoundFontError::NotLoaded);
+    };
+    *preset = font
+        .find_preset(bank, program)
+        .ok_or(SoundFontError::PresetNotFound { bank, program })?;
+    update_audio_out_instrument(&state);
+    Ok(())
+}
+
+fn update_audio_out_instrument(state: &AppState) {
+    if let Some(audio_out) = &state.audio_out {
+        audio_out.set_instrument(state.instrument.clone());
     }
+}
 
-    fn take_raw(self) -> [u8; 3] {
-        self.message
+/// The preset being played, or `None` with the built-in piano.
+#[tauri::command]
+fn get_soundfont_preset(app: AppHandle) -> Option<PresetInfo> {
+    let state: State<'_, Mutex<AppState>> = app.state();
+    let state = state.lock().unwrap();
+    match &state.instrument {
+        Instrument::Piano => None,
+        Instrument::SoundFont { font, preset } => Some(font.preset_info(*preset)),
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-struct PackedPlaybackEvent {
-    is_note_on: bool,
-    message: [u8; 3],
-    time_length: u32,
-}
-
-impl PlaybackEvent {
-    fn from_track_event(track_event: &TrackEvent) -> Self {
-        if let TrackEventKind::Midi { channel, message } = track_event.kind {
-            let event = LiveEvent::Midi {
-                channel,
-                message,
-            };
+#[tauri::command]
+fn list_recordings(app: AppHandle) -> Result<Vec<RecordingEntry>, LibraryError> {
+    Ok(Library::open(recordings_dir(&app)?)?.list())
+}
 
-            let is_note_on = match message {
-                midly::MidiMessage::NoteOn { .. } => true,
-                _ => false,
-            };
+#[tauri::command]
+fn update_recording_metadata(
+    app: AppHandle,
+    id: u64,
+    update: RecordingMetadataUpdate,
+) -> Result<RecordingEntry, LibraryError> {
+    Library::open(recordings_dir(&app)?)?.update(id, update)
+}
 
-            // Create a buffer to hold the raw MIDI bytes
-            let mut buf = Vec::with_capacity(3);
+#[tauri::command]
+fn rename_recording(app: AppHandle, id: u64, name: String) -> Result<RecordingEntry, LibraryError> {
+    Library::open(recordings_dir(&app)?)?.rename(id, &name)
+}
 
-            // Write the event's raw bytes into the buffer
-            event.write(&mut buf).expect("Failed to write MIDI event");
+#[tauri::command]
+fn delete_recording(app: AppHandle, id: u64) -> Result<(), LibraryError> {
+    Library::open(recordings_dir(&app)?)?.delete(id)
+}
 
-            PlaybackEvent {
-                delta: track_event.delta.as_int(),
-                message: [buf[0], buf[1], buf[2]],
-                is_note_on,
-                time_length: 0,
+#[tauri::command]
+async fn playback_midi_file(app: AppHandle, path: String, tracks: Option<TrackSelection>) -> bool {
+    let Ok(data) = std::fs::read(path) else {
+        return false;
+    };
+    let Ok(smf) = Smf::parse(&data) else {
+        return false;
+    };
+    let song = Song::from_smf(&smf);
+    let smf = smf.make_static();
+
+    let state: State<'_, Mutex<AppState>> = app.state();
+    let mut state = state.lock().unwrap();
+
+    if let Some(playback) = state.playback.take() {
+        playback.stop();
+    }
+    state.scoring = None;
+    state.playback_file = Some(smf);
+
+    // With no output chosen and no internal synth playing, play to the first
+    // output there is rather than to nothing.
+    if state.audio_out.is_none() {
+        if let MidiOutState::Disconnected(midi_out) = &state.midi_out_state {
+            let port_id = midi_out.as_ref().unwrap().ports().first().map(|port| port.id());
+            if let Some(port_id) = port_id {
+                state.midi_out_state.connect_with_id(port_id, app.clone());
             }
-        } else {
-            panic!("Invalid TrackEventKind");
         }
     }
+
+    state.playback = Some(PlaybackEngine::start(
+        app.clone(),
+        song,
+        tracks.unwrap_or_default(),
+        state.playback_settings,
+        state.wait_mode.clone(),
+    ));
+    true
 }
 
 #[tauri::command]
-async fn playback_midi_file(app: AppHandle, path: String) -> bool {
-    let app_handle = app.clone();
-    let app_state = app.clone();
-    {
-        let state: State<'_, Mutex<AppState>> = app_state.state();
-        let mut state = state.lock().unwrap();
+fn pause_playback(app: AppHandle) -> bool {
+    let state: State<'_, Mutex<AppState>> = app.state();
+    let state = state.lock().unwrap();
+    state.playback.as_ref().is_some_and(|playback| playback.pause())
+}
 
-        let out_id = match &state.midi_out_state {
-            MidiOutState::Connected(conn) => {
-                return false;
-            },
-            MidiOutState::Disconnected(midi_out) => {
-                let ports = midi_out.as_ref().unwrap().ports();
-                let available_port = ports.iter().next().unwrap();
-        
-                available_port.id()
-            }
-        };
-        
+#[tauri::command]
+fn resume_playback(app: AppHandle) -> bool {
+    let state: State<'_, Mutex<AppState>> = app.state();
+    let state = state.lock().unwrap();
+    state.playback.as_ref().is_some_and(|playback| playback.resume())
+}
 
-        state.midi_out_state.connect_with_id(out_id, app_handle.clone());
+#[tauri::command]
+fn seek_playback(app: AppHandle, ms: u64) -> bool {
+    let state: State<'_, Mutex<AppState>> = app.state();
+    let state = state.lock().unwrap();
+    state.playback.as_ref().is_some_and(|playback| playback.seek(ms))
+}
+
+#[tauri::command]
+fn stop_playback(app: AppHandle) -> bool {
+    let state: State<'_, Mutex<AppState>> = app.state();
+    let mut state = state.lock().unwrap();
+    match state.playback.take() {
+        Some(playback) => {
+            playback.stop();
+            true
+        }
+        None => false,
     }
+}
 
+#[tauri::command]
+fn set_playback_speed(app: AppHandle, speed: f64) -> bool {
+    if !PlaybackSettings::SPEED_RANGE.contains(&speed) {
+        return false;
+    }
 
+    let state: State<'_, Mutex<AppState>> = app.state();
+    let mut state = state.lock().unwrap();
+    state.playback_settings.speed = speed;
+    if let Some(playback) = &state.playback {
+        playback.set_settings(state.playback_settings);
+    }
+    true
+}
 
+#[tauri::command]
+fn set_playback_transpose(app: AppHandle, semitones: i8) -> bool {
+    if !PlaybackSettings::TRANSPOSE_RANGE.contains(&semitones) {
+        return false;
+    }
 
-   let join_handle = std::thread::spawn(move || {
-        let data = std::fs::read(path).expect("Error while reading MIDI file");
-        let smf = Smf::parse(&data).expect("Error while parsing MIDI file");
+    let state: State<'_, Mutex<AppState>> = app.state();
+    let mut state = state.lock().unwrap();
+    state.playback_settings.transpose = semitones;
+    if let Some(playback) = &state.playback {
+        playback.set_settings(state.playback_settings);
+    }
+    true
+}
 
-        if smf.tracks.is_empty() {
-            return false;
-        }
+#[tauri::command]
+fn get_playback_settings(app: AppHandle) -> PlaybackSettings {
+    let state: State<'_, Mutex<AppState>> = app.state();
+    let state = state.lock().unwrap();
+    state.playback_settings
+}
 
-        // u8 is note, u32 is start time, usize is index in playback_track
-        let mut events_map: HashMap<u8, (u32, usize)> = HashMap::new();
-        let mut playback_track: Vec<PlaybackEvent> = Vec::new();
-        let mut elapsed_time = 0u32;
-
-        for event in smf.tracks[0].iter() {
-            elapsed_time += event.delta.as_int();
-            if let TrackEventKind::Midi { message,.. } = event.kind {
-                let playback_event = PlaybackEvent::from_track_event(&event);
-                playback_track.push(playback_event);
-
-                if let midly::MidiMessage::NoteOn { key, .. } = message {
-                    events_map.insert(key.as_int(), (elapsed_time, playback_track.len() - 1));
-                } else if let midly::MidiMessage::NoteOff { key, .. } = message {
-                    if let Some((start_time, index)) = events_map.remove(&key.as_int()) {
-                        let time_length = elapsed_time - start_time;
-                        playback_track[index].time_length = time_length;
-                    }
-                }
-            }
-        }
+#[tauri::command]
+fn set_playback_loop(
+    app: AppHandle,
+    start: LoopPoint,
+    end: LoopPoint,
+    gap_ms: Option<u64>,
+) -> bool {
+    let state: State<'_, Mutex<AppState>> = app.state();
+    let state = state.lock().unwrap();
+    state
+        .playback
+        .as_ref()
+        .is_some_and(|playback| playback.set_loop(start, end, gap_ms.unwrap_or(0)))
+}
 
-        
+#[tauri::command]
+fn clear_playback_loop(app: AppHandle) -> bool {
+    let state: State<'_, Mutex<AppState>> = app.state();
+    let state = state.lock().unwrap();
+    state
+        .playback
+        .as_ref()
+        .is_some_and(|playback| playback.clear_loop())
+}
 
-        // Keep track of elapsed time
-        let playback_start = std::time::Instant::now();
-        let mut cumulative_delta = 0u64; // Cumulative delta time in milliseconds
+#[tauri::command]
+fn set_wait_mode(app: AppHandle, settings: Option<WaitModeSettings>) -> bool {
+    let state: State<'_, Mutex<AppState>> = app.state();
+    let mut state = state.lock().unwrap();
+    state.wait_mode = settings;
+    if let Some(playback) = &state.playback {
+        playback.set_wait_mode(state.wait_mode.clone());
+    }
+    true
+}
 
-        let track = &smf.tracks[0];
+#[tauri::command]
+fn start_scoring(app: AppHandle, settings: Option<ScoringSettings>) -> bool {
+    let state: State<'_, Mutex<AppState>> = app.state();
+    let mut state = state.lock().unwrap();
+    let Some((pass, start)) = state
+        .playback
+        .as_ref()
+        .map(|playback| playback.pass_position())
+    else {
+        return false;
+    };
+    state.scoring = Some(ScoringSession::new(
+        settings.unwrap_or_default(),
+        pass,
+        start,
+    ));
+    true
+}
 
-        for (i, event) in playback_track.iter().enumerate() {
-            cumulative_delta += event.delta as u64;
+#[tauri::command]
+fn stop_scoring(app: AppHandle) -> Option<ScoreReport> {
+    let state: State<'_, Mutex<AppState>> = app.state();
+    let mut state = state.lock().unwrap();
+    let scoring = state.scoring.take()?;
+    let playback = state.playback.as_ref()?;
+    Some(scoring.finish(
+        playback.song(),
+        playback.tracks(),
+        &playback.jumps(),
+        playback.position(),
+    ))
+}
 
-            // Calculate the target playback time for the current event
-            let target_time = std::time::Duration::from_millis(cumulative_delta);
-            let elapsed_time = playback_start.elapsed();
+/// Scores the recording at `recording_path` against the reference file.
+///
+/// The recording is shifted by `offset_ms`, or so that its first note falls
+/// on the first note of the reference, unless it is an overdub of the
+/// reference and already on its clock. Metronome clicks and the reference
+/// tracks an overdub carries along are not counted as played.
+#[tauri::command]
+fn score_recording(
+    reference_path: String,
+    recording_path: String,
+    settings: Option<ScoringSettings>,
+    offset_ms: Option<i64>,
+) -> Result<ScoreReport, ScoreError> {
+    let settings = settings.unwrap_or_default();
+
+    let reference_data = std::fs::read(reference_path)?;
+    let reference_smf = Smf::parse(&reference_data)?;
+    let recording_data = std::fs::read(recording_path)?;
+    let recording_smf = Smf::parse(&recording_data)?;
+
+    let is_overdub = recording_smf.tracks.len() > reference_smf.tracks.len()
+        && recording_smf.tracks.starts_with(&reference_smf.tracks);
+    let first_track = if is_overdub {
+        reference_smf.tracks.len()
+    } else {
+        0
+    };
+    let played_tracks = TrackSelection {
+        play: Some(
+            (first_track..recording_smf.tracks.len())
+                .filter(|track| !is_click_track(&recording_smf.tracks[*track]))
+                .collect(),
+        ),
+        ..TrackSelection::default()
+    };
 
-            // Wait until the target time is reached
-            if target_time > elapsed_time {
-                std::thread::sleep(target_time - elapsed_time);
-            }
+    let reference = Song::from_smf(&reference_smf);
+    let recording = Song::from_smf(&recording_smf);
+    let expected = ScoredNote::from_song(&reference, &TrackSelection::default(), &settings);
+    let mut played = ScoredNote::from_song(&recording, &played_tracks, &ScoringSettings::default());
+    align(
+        &expected,
+        &mut played,
+        offset_ms.or(is_overdub.then_some(0)),
+    );
+    Ok(score(&expected, &played, settings.tolerance_ms))
+}
 
-            app.emit("future_piano_event", event.to_packed()).expect("Error while emitting piano event");
+#[tauri::command]
+fn set_midi_thru(app: AppHandle, settings: ThruSettings) -> bool {
+    if !settings.is_valid() {
+        return false;
+    }
 
-            let track_event = &track[i];
-            if let TrackEventKind::Midi { channel, message } = &track_event.kind {
-                let event = LiveEvent::Midi {
-                    channel: *channel,
-                    message: message.clone(),
-                };
+    let state: State<'_, Mutex<AppState>> = app.state();
+    let mut state = state.lock().unwrap();
+    state.thru = settings;
+    true
+}
 
-                let mut buf = Vec::with_capacity(3);
-                event.write(&mut buf).expect("Failed to write MIDI event");
+#[tauri::command]
+fn get_midi_thru(app: AppHandle) -> ThruSettings {
+    let state: State<'_, Mutex<AppState>> = app.state();
+    let state = state.lock().unwrap();
+    state.thru.clone()
+}
 
-                let event = [buf[0], buf[1], buf[2]];
+#[tauri::command]
+fn start_metronome(app: AppHandle) -> bool {
+    let state: State<'_, Mutex<AppState>> = app.state();
+    let mut state = state.lock().unwrap();
+    if state.metronome.is_some() {
+        return false;
+    }
 
-                println!("I: {}, len: {}", i, track.len());
+    let click_app = app.clone();
+    state.metronome = Some(Metronome::start(
+        app.clone(),
+        state.metronome_settings.clone(),
+        Instant::now(),
+        move |message| send_click(&click_app, message),
+    ));
+    true
+}
 
-                app.emit("future_piano_playback", event).expect("Error while emitting piano event");
-            }
+#[tauri::command]
+fn stop_metronome(app: AppHandle) -> bool {
+    let state: State<'_, Mutex<AppState>> = app.state();
+    // The metronome thread locks the state to send its clicks.
+    let metronome = state.lock().unwrap().metronome.take();
+    match metronome {
+        Some(metronome) => {
+            metronome.stop();
+            true
         }
-        true
-    });
+        None => false,
+    }
+}
 
-    
+/// Changes the metronome settings, restarting the metronome if it is running.
+#[tauri::command]
+fn set_metronome_settings(app: AppHandle, settings: MetronomeSettings) -> bool {
+    if !settings.is_valid() {
+        return false;
+    }
 
-    join_handle.join().expect("Error while joining thread");
+    let state: State<'_, Mutex<AppState>> = app.state();
+    state.lock().unwrap().metronome_settings = settings;
+    if stop_metronome(app.clone()) {
+        start_metronome(app);
+    }
+    true
+}
 
-    std::thread::sleep(std::time::Duration::from_secs(5));
+#[tauri::command]
+fn get_metronome_settings(app: AppHandle) -> MetronomeSettings {
+    let state: State<'_, Mutex<AppState>> = app.state();
+    let state = state.lock().unwrap();
+    state.metronome_settings.clone()
+}
 
-    let state: State<'_, Mutex<AppState>> = app_handle.state();
+/// Plays a metronome click on the MIDI output and records it if the
+/// recording keeps a click track.
+fn send_click(app: &AppHandle, message: [u8; 3]) {
+    let state: State<'_, Mutex<AppState>> = app.state();
     let mut state = state.lock().unwrap();
-    state.midi_out_state.close();
+    state.midi_out_state.send_out(&message, app);
 
-    true
+    if let Some(recorder) = &mut state.recording {
+        if let Ok(LiveEvent::Midi { channel, message }) = LiveEvent::parse(&message) {
+            recorder.record_click(channel, message);
+        }
+    }
+}
+
+#[tauri::command]
+fn get_playback_state(app: AppHandle) -> Option<PlaybackStateEvent> {
+    let state: State<'_, Mutex<AppState>> = app.state();
+    let state = state.lock().unwrap();
+    state.playback.as_ref().map(|playback| playback.state())
+}
+
+#[tauri::command]
+fn get_midi_file_tracks(path: String) -> Option<Vec<MidiTrackInfo>> {
+    let data = std::fs::read(path).ok()?;
+    let smf = Smf::parse(&data).ok()?;
+    Some(track_info(&smf))
 }
 
 #[tauri::command]
@@ -958,13 +1695,149 @@ async fn playback_midi_event(app: AppHandle, ev: [u8; 3]) -> bool {
     let state: State<'_, Mutex<AppState>> = app.state();
     let mut state = state.lock().unwrap();
     
-    match &mut state.midi_out_state {
-        MidiOutState::Connected(midi_out) => {
-            midi_out.as_mut().unwrap().0.send(&ev).expect("error while sending midi data");
-            return true;
-        },
-        MidiOutState::Disconnected(_) => {
-            return false;
+    // Program change and channel pressure only carry one data byte.
+    let len = match ev[0] & 0xF0 {
+        0xC0 | 0xD0 => 2,
+        _ => 3,
+    };
+    if let Some(audio_out) = &state.audio_out {
+        audio_out.send(&ev[..len]);
+    }
+    let played = state.audio_out.is_some();
+
+    state.midi_out_state.send_out(&ev[..len], &app) || played
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(parser: &mut MidiMessageParser, msg: &[u8]) -> Result<LiveEvent<'static>, ParseError> {
+        parser.parse(msg).map(|event| event.to_live_event())
+    }
+
+    fn note_on(channel: u8, key: u8, vel: u8) -> LiveEvent<'static> {
+        LiveEvent::Midi {
+            channel: u4::new(channel),
+            message: MidiMessage::NoteOn {
+                key: u7::new(key),
+                vel: u7::new(vel),
+            },
         }
     }
-}
\ No newline at end of file
+
+    fn note_off(channel: u8, key: u8) -> LiveEvent<'static> {
+        LiveEvent::Midi {
+            channel: u4::new(channel),
+            message: MidiMessage::NoteOff {
+                key: u7::new(key),
+                vel: u7::new(0),
+            },
+        }
+    }
+
+    #[test]
+    fn parses_running_status() {
+        let mut parser = MidiMessageParser::new("input".to_string());
+
+        assert_eq!(
+            parse(&mut parser, &[0x92, 60, 100]),
+            Ok(note_on(2, 60, 100))
+        );
+        assert_eq!(parse(&mut parser, &[64, 90]), Ok(note_on(2, 64, 90)));
+        // Velocity 0 under running status releases the chord.
+        assert_eq!(parse(&mut parser, &[60, 0]), Ok(note_off(2, 60)));
+    }
+
+    #[test]
+    fn real_time_messages_keep_running_status() {
+        let mut parser = MidiMessageParser::new("input".to_string());
+
+        parse(&mut parser, &[0x90, 60, 100]).unwrap();
+        assert_eq!(
+            parse(&mut parser, &[0xF8]),
+            Err(ParseError::Unsupported(0xF8))
+        );
+        assert_eq!(parse(&mut parser, &[62, 100]), Ok(note_on(0, 62, 100)));
+    }
+
+    #[test]
+    fn system_common_messages_cancel_running_status() {
+        let mut parser = MidiMessageParser::new("input".to_string());
+
+        parse(&mut parser, &[0x90, 60, 100]).unwrap();
+        assert_eq!(
+            parse(&mut parser, &[0xF2, 0, 0]),
+            Err(ParseError::Unsupported(0xF2))
+        );
+        assert_eq!(
+            parse(&mut parser, &[62, 100]),
+            Err(ParseError::MissingStatus)
+        );
+    }
+
+    #[test]
+    fn parses_one_byte_messages() {
+        let mut parser = MidiMessageParser::new("input".to_string());
+
+        assert_eq!(
+            parse(&mut parser, &[0xC1, 5]),
+            Ok(LiveEvent::Midi {
+                channel: u4::new(1),
+                message: MidiMessage::ProgramChange {
+                    program: u7::new(5)
+                },
+            })
+        );
+        assert_eq!(
+            parse(&mut parser, &[0xE0, 0x00, 0x40]),
+            Ok(LiveEvent::Midi {
+                channel: u4::new(0),
+                message: MidiMessage::PitchBend {
+                    bend: midly::PitchBend(u14::new(0x2000)),
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_messages() {
+        let mut parser = MidiMessageParser::new("input".to_string());
+
+        assert_eq!(parse(&mut parser, &[]), Err(ParseError::Empty));
+        assert_eq!(
+            parse(&mut parser, &[0x90, 60]),
+            Err(ParseError::Truncated {
+                status: 0x90,
+                expected: 2,
+                found: 1,
+            })
+        );
+        assert_eq!(
+            parse(&mut parser, &[0xB0, 64, 200]),
+            Err(ParseError::UnexpectedStatus(200))
+        );
+    }
+
+    #[test]
+    fn status_byte_in_place_of_a_key_is_not_a_note_out_of_range() {
+        let mut parser = MidiMessageParser::new("input".to_string());
+
+        assert_eq!(
+            parse(&mut parser, &[0x90, 0x80, 100]),
+            Err(ParseError::UnexpectedStatus(0x80))
+        );
+        assert_eq!(
+            parse(&mut parser, &[0xA0, 60, 0xF8]),
+            Err(ParseError::UnexpectedStatus(0xF8))
+        );
+    }
+
+    #[test]
+    fn tags_events_with_their_source() {
+        let mut parser = MidiMessageParser::new("keyboard".to_string());
+
+        let event = parser.parse(&[0x90, 60, 100]).unwrap();
+        assert_eq!(event.source, "keyboard");
+    }
+}
@@ -0,0 +1,547 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use midly::Smf;
+use serde::{Deserialize, Serialize};
+
+use crate::{playback::Song, tempo::TempoMap};
+
+const INDEX_FILE: &str = "index.json";
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+pub enum LibraryError {
+    /// The app data directory could not be resolved.
+    NoDataDirectory(String),
+    NotFound(u64),
+    /// A recording with the requested file name already exists.
+    AlreadyExists(String),
+    InvalidName(String),
+    InvalidIndex(String),
+    Io(String),
+}
+
+impl std::fmt::Display for LibraryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LibraryError::NoDataDirectory(err) => {
+                write!(f, "app data directory not found: {}", err)
+            }
+            LibraryError::NotFound(id) => write!(f, "no recording with id {}", id),
+            LibraryError::AlreadyExists(name) => {
+                write!(f, "a recording named {} already exists", name)
+            }
+            LibraryError::InvalidName(name) => write!(f, "invalid recording name {:?}", name),
+            LibraryError::InvalidIndex(err) => write!(f, "invalid recordings index: {}", err),
+            LibraryError::Io(err) => write!(f, "error while accessing recordings: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for LibraryError {}
+
+impl From<std::io::Error> for LibraryError {
+    fn from(err: std::io::Error) -> Self {
+        LibraryError::Io(err.to_string())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RecordingEntry {
+    id: u64,
+    path: PathBuf,
+    /// Seconds since the Unix epoch.
+    created: u64,
+    duration_ms: u64,
+    note_count: usize,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    starred: bool,
+    #[serde(default)]
+    notes: String,
+}
+
+/// Changes to a recording's metadata; fields left out are kept as they are.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct RecordingMetadataUpdate {
+    pub tags: Option<Vec<String>>,
+    pub starred: Option<bool>,
+    pub notes: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+struct Index {
+    next_id: u64,
+    recordings: Vec<RecordingEntry>,
+}
+
+/// The recordings directory and its index of metadata.
+///
+/// The index is read when the library is opened and written back by every
+/// change. Files that have disappeared are dropped from it, and MIDI files
+/// put into the directory by hand are added to it.
+pub struct Library {
+    dir: PathBuf,
+    index: Index,
+}
+
+impl Library {
+    pub fn open(dir: PathBuf) -> Result<Self, LibraryError> {
+        fs::create_dir_all(&dir)?;
+
+        let index = match fs::read(dir.join(INDEX_FILE)) {
+            Ok(data) => serde_json::from_slice(&data)
+                .map_err(|err| LibraryError::InvalidIndex(err.to_string()))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Index::default(),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut library = Library { dir, index };
+        let mut changed = library.prune();
+        changed |= library.scan()?;
+        if changed {
+            library.save()?;
+        }
+        Ok(library)
+    }
+
+    fn save(&self) -> Result<(), LibraryError> {
+        let data = serde_json::to_vec_pretty(&self.index)
+            .map_err(|err| LibraryError::InvalidIndex(err.to_string()))?;
+        fs::write(self.dir.join(INDEX_FILE), data)?;
+        Ok(())
+    }
+
+    /// Drops entries whose file no longer exists.
+    fn prune(&mut self) -> bool {
+        let count = self.index.recordings.len();
+        self.index.recordings.retain(|entry| entry.path.exists());
+        self.index.recordings.len() != count
+    }
+
+    /// Indexes MIDI files in the directory that are not in the index yet.
+    fn scan(&mut self) -> Result<bool, LibraryError> {
+        let mut changed = false;
+        for dir_entry in fs::read_dir(&self.dir)? {
+            let path = dir_entry?.path();
+            let is_midi = path
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("mid"));
+            if !is_midi || self.index.recordings.iter().any(|entry| entry.path == path) {
+                continue;
+            }
+
+            let Ok(data) = fs::read(&path) else {
+                continue;
+            };
+            let Ok(smf) = Smf::parse(&data) else {
+                continue;
+            };
+            let created = fs::metadata(&path)
+                .and_then(|metadata| metadata.modified())
+                .map_or(0, unix_secs);
+            self.push(path, &smf, created);
+            changed = true;
+        }
+        Ok(changed)
+    }
+
+    fn push(&mut self, path: PathBuf, smf: &Smf, created: u64) -> &RecordingEntry {
+        self.index.recordings.push(RecordingEntry {
+            id: self.index.next_id,
+            path,
+            created,
+            duration_ms: duration(smf) / 1000,
+            note_count: note_count(smf),
+            tags: vec![],
+            starred: false,
+            notes: String::new(),
+        });
+        self.index.next_id += 1;
+        self.index.recordings.last().unwrap()
+    }
+
+    /// Adds a recording that has just been saved to `path`.
+    ///
+    /// Opening the library already indexes new files in its directory, and a
+    /// file may be overwritten, so an entry for `path` is updated rather than
+    /// added twice.
+    pub fn add(&mut self, path: PathBuf, smf: &Smf) -> Result<RecordingEntry, LibraryError> {
+        let created = unix_secs(SystemTime::now());
+        let entry = match self
+            .index
+            .recordings
+            .iter_mut()
+            .find(|entry| entry.path == path)
+        {
+            Some(entry) => {
+                entry.created = created;
+                entry.duration_ms = duration(smf) / 1000;
+                entry.note_count = note_count(smf);
+                entry.clone()
+            }
+            None => self.push(path, smf, created).clone(),
+        };
+        self.save()?;
+        Ok(entry)
+    }
+
+    /// Every recording, newest first.
+    pub fn list(&self) -> Vec<RecordingEntry> {
+        let mut recordings = self.index.recordings.clone();
+        recordings.sort_by_key(|entry| std::cmp::Reverse(entry.created));
+        recordings
+    }
+
+    fn get_mut(&mut self, id: u64) -> Result<&mut RecordingEntry, LibraryError> {
+        self.index
+            .recordings
+            .iter_mut()
+            .find(|entry| entry.id == id)
+            .ok_or(LibraryError::NotFound(id))
+    }
+
+    pub fn update(
+        &mut self,
+        id: u64,
+        update: RecordingMetadataUpdate,
+    ) -> Result<RecordingEntry, LibraryError> {
+        let entry = self.get_mut(id)?;
+        if let Some(tags) = update.tags {
+            entry.tags = tags;
+        }
+        if let Some(starred) = update.starred {
+            entry.starred = starred;
+        }
+        if let Some(notes) = update.notes {
+            entry.notes = notes;
+        }
+
+        let entry = entry.clone();
+        self.save()?;
+        Ok(entry)
+    }
+
+    /// Renames the recording's file, keeping it in the same directory.
+    pub fn rename(&mut self, id: u64, name: &str) -> Result<RecordingEntry, LibraryError> {
+        let is_valid = !name.trim().is_empty()
+            && !name.contains(['/', '\\'])
+            && Path::new(name).file_name() == Some(name.as_ref());
+        if !is_valid {
+            return Err(LibraryError::InvalidName(name.to_string()));
+        }
+
+        let entry = self.get_mut(id)?;
+        let file_name = if name.to_ascii_lowercase().ends_with(".mid") {
+            name.to_string()
+        } else {
+            format!("{}.mid", name)
+        };
+        let path = entry.path.with_file_name(&file_name);
+        if path.exists() {
+            return Err(LibraryError::AlreadyExists(file_name));
+        }
+
+        fs::rename(&entry.path, &path)?;
+        entry.path = path;
+
+        let entry = entry.clone();
+        self.save()?;
+        Ok(entry)
+    }
+
+    /// Deletes the recording's file and its entry.
+    pub fn delete(&mut self, id: u64) -> Result<(), LibraryError> {
+        let path = self.get_mut(id)?.path.clone();
+        match fs::remove_file(&path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+
+        self.index.recordings.retain(|entry| entry.id != id);
+        self.save()
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// Length of the file in microseconds, up to its last event of any kind.
+fn duration(smf: &Smf) -> u64 {
+    let end_tick = smf
+        .tracks
+        .iter()
+        .map(|track| {
+            track
+                .iter()
+                .map(|event| event.delta.as_int() as u64)
+                .sum::<u64>()
+        })
+        .max()
+        .unwrap_or(0);
+    TempoMap::from_smf(smf).tick_to_micros(end_tick)
+}
+
+fn note_count(smf: &Smf) -> usize {
+    Song::from_smf(smf)
+        .timeline
+        .iter()
+        .filter(|event| event.is_note_on)
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use midly::{
+        num::{u15, u28, u4, u7},
+        Format, Header, MetaMessage, MidiMessage, Timing, TrackEvent, TrackEventKind,
+    };
+
+    use super::*;
+
+    /// A directory of its own for every test, removed when the test ends.
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("library-test-{}-{}", name, std::process::id()));
+            fs::remove_dir_all(&dir).ok();
+            TestDir(dir)
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            fs::remove_dir_all(&self.0).ok();
+        }
+    }
+
+    /// A file of `notes` quarter notes on middle C.
+    fn smf(notes: u32) -> Smf<'static> {
+        let mut smf = Smf::new(Header::new(
+            Format::SingleTrack,
+            Timing::Metrical(u15::new(480)),
+        ));
+        let mut track = vec![];
+        for _ in 0..notes {
+            for (delta, vel) in [(0, 100), (480, 0)] {
+                track.push(TrackEvent {
+                    delta: u28::new(delta),
+                    kind: TrackEventKind::Midi {
+                        channel: u4::new(0),
+                        message: MidiMessage::NoteOn {
+                            key: u7::new(60),
+                            vel: u7::new(vel),
+                        },
+                    },
+                });
+            }
+        }
+        track.push(TrackEvent {
+            delta: u28::new(0),
+            kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+        });
+        smf.tracks.push(track);
+        smf
+    }
+
+    fn write_file(dir: &Path, name: &str, notes: u32) -> PathBuf {
+        fs::create_dir_all(dir).unwrap();
+        let path = dir.join(name);
+        smf(notes).save(&path).unwrap();
+        path
+    }
+
+    fn paths(library: &Library) -> Vec<PathBuf> {
+        let mut paths = library
+            .list()
+            .into_iter()
+            .map(|entry| entry.path)
+            .collect::<Vec<_>>();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn indexes_midi_files_found_in_the_directory() {
+        let dir = TestDir::new("scan");
+        let a = write_file(&dir.0, "a.mid", 2);
+        let b = write_file(&dir.0, "b.MID", 1);
+        fs::write(dir.0.join("broken.mid"), b"not a MIDI file").unwrap();
+        fs::write(dir.0.join("notes.txt"), b"").unwrap();
+
+        let library = Library::open(dir.0.clone()).unwrap();
+        assert_eq!(paths(&library), vec![a.clone(), b]);
+
+        let entry = library
+            .list()
+            .into_iter()
+            .find(|entry| entry.path == a)
+            .unwrap();
+        assert_eq!(entry.note_count, 2);
+        assert_eq!(entry.duration_ms, 1000);
+        assert!(dir.0.join(INDEX_FILE).exists());
+    }
+
+    #[test]
+    fn prunes_files_that_have_disappeared() {
+        let dir = TestDir::new("prune");
+        let a = write_file(&dir.0, "a.mid", 1);
+        let b = write_file(&dir.0, "b.mid", 1);
+        Library::open(dir.0.clone()).unwrap();
+
+        fs::remove_file(&a).unwrap();
+        let library = Library::open(dir.0.clone()).unwrap();
+        assert_eq!(paths(&library), vec![b.clone()]);
+
+        // The pruned index was written back.
+        let index: Index =
+            serde_json::from_slice(&fs::read(dir.0.join(INDEX_FILE)).unwrap()).unwrap();
+        let indexed = index
+            .recordings
+            .into_iter()
+            .map(|entry| entry.path)
+            .collect::<Vec<_>>();
+        assert_eq!(indexed, vec![b]);
+    }
+
+    #[test]
+    fn updates_survive_reopening_the_index() {
+        let dir = TestDir::new("update");
+        write_file(&dir.0, "a.mid", 1);
+        let mut library = Library::open(dir.0.clone()).unwrap();
+        let id = library.list()[0].id;
+
+        library
+            .update(
+                id,
+                RecordingMetadataUpdate {
+                    tags: Some(vec!["scales".to_string()]),
+                    starred: Some(true),
+                    ..RecordingMetadataUpdate::default()
+                },
+            )
+            .unwrap();
+        // Fields left out are kept.
+        library
+            .update(
+                id,
+                RecordingMetadataUpdate {
+                    notes: Some("too fast".to_string()),
+                    ..RecordingMetadataUpdate::default()
+                },
+            )
+            .unwrap();
+
+        let mut library = Library::open(dir.0.clone()).unwrap();
+        let entry = &library.list()[0];
+        assert_eq!(entry.id, id);
+        assert_eq!(entry.tags, vec!["scales".to_string()]);
+        assert!(entry.starred);
+        assert_eq!(entry.notes, "too fast");
+
+        // New recordings never reuse an id.
+        let b = write_file(&dir.0, "b.mid", 1);
+        let added = library.add(b, &smf(1)).unwrap();
+        assert_ne!(added.id, id);
+    }
+
+    #[test]
+    fn adding_a_saved_file_again_updates_its_entry() {
+        let dir = TestDir::new("add");
+        let a = write_file(&dir.0, "a.mid", 1);
+        let mut library = Library::open(dir.0.clone()).unwrap();
+        let id = library.list()[0].id;
+
+        smf(3).save(&a).unwrap();
+        let entry = library.add(a.clone(), &smf(3)).unwrap();
+
+        assert_eq!(entry.id, id);
+        assert_eq!(entry.note_count, 3);
+        assert_eq!(paths(&Library::open(dir.0.clone()).unwrap()), vec![a]);
+    }
+
+    #[test]
+    fn renames_files_in_place() {
+        let dir = TestDir::new("rename");
+        let a = write_file(&dir.0, "a.mid", 1);
+        let mut library = Library::open(dir.0.clone()).unwrap();
+        let id = library.list()[0].id;
+
+        let entry = library.rename(id, "etude").unwrap();
+        assert_eq!(entry.path, dir.0.join("etude.mid"));
+        assert!(!a.exists());
+        assert!(entry.path.exists());
+
+        let library = Library::open(dir.0.clone()).unwrap();
+        assert_eq!(library.list()[0].id, id);
+        assert_eq!(paths(&library), vec![dir.0.join("etude.mid")]);
+    }
+
+    #[test]
+    fn rename_never_overwrites_another_recording() {
+        let dir = TestDir::new("rename-collision");
+        let a = write_file(&dir.0, "a.mid", 1);
+        let b = write_file(&dir.0, "b.mid", 2);
+        let mut library = Library::open(dir.0.clone()).unwrap();
+        let id = library
+            .list()
+            .into_iter()
+            .find(|entry| entry.path == a)
+            .unwrap()
+            .id;
+
+        assert!(matches!(
+            library.rename(id, "b"),
+            Err(LibraryError::AlreadyExists(name)) if name == "b.mid"
+        ));
+        assert!(a.exists());
+        assert_eq!(note_count(&Smf::parse(&fs::read(&b).unwrap()).unwrap()), 2);
+
+        for name in ["", " ", "../a", "sub/a", "..", "sub\\a"] {
+            assert!(
+                matches!(library.rename(id, name), Err(LibraryError::InvalidName(_))),
+                "{:?}",
+                name
+            );
+        }
+        assert_eq!(paths(&library), vec![a, b]);
+    }
+
+    #[test]
+    fn deletes_the_file_and_its_entry() {
+        let dir = TestDir::new("delete");
+        let a = write_file(&dir.0, "a.mid", 1);
+        let b = write_file(&dir.0, "b.mid", 1);
+        let mut library = Library::open(dir.0.clone()).unwrap();
+        let id = |library: &Library, path: &Path| {
+            library
+                .list()
+                .into_iter()
+                .find(|entry| entry.path == path)
+                .unwrap()
+                .id
+        };
+
+        library.delete(id(&library, &a)).unwrap();
+        assert!(!a.exists());
+        // A file that is already gone is deleted all the same.
+        let b_id = id(&library, &b);
+        fs::remove_file(&b).unwrap();
+        library.delete(b_id).unwrap();
+        assert!(matches!(
+            library.delete(b_id),
+            Err(LibraryError::NotFound(_))
+        ));
+
+        assert!(Library::open(dir.0.clone()).unwrap().list().is_empty());
+    }
+}
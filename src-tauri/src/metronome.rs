@@ -0,0 +1,271 @@
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter};
+
+/// General MIDI percussion channel (channel 10).
+const CLICK_CHANNEL: u8 = 9;
+const ACCENT_VELOCITY: u8 = 127;
+const BEAT_VELOCITY: u8 = 96;
+const SUBDIVISION_VELOCITY: u8 = 64;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct MetronomeSettings {
+    /// Tempo in quarter notes per minute, as in the recording settings.
+    pub bpm: f64,
+    /// Time signature as (numerator, denominator); the metronome clicks once
+    /// per denominator note, e.g. six times per bar in 6/8.
+    pub time_signature: (u8, u8),
+    /// Beats of the bar that are accented, counting from 0.
+    pub accents: Vec<u8>,
+    /// Clicks per beat, e.g. 2 for eighth notes in 4/4.
+    pub subdivision: u8,
+    /// Key played on accented beats; the General MIDI high wood block.
+    pub accent_key: u8,
+    /// Key played on other beats and subdivisions; the low wood block.
+    pub beat_key: u8,
+}
+
+impl Default for MetronomeSettings {
+    fn default() -> Self {
+        MetronomeSettings {
+            bpm: 120.0,
+            time_signature: (4, 4),
+            accents: vec![0],
+            subdivision: 1,
+            accent_key: 76,
+            beat_key: 77,
+        }
+    }
+}
+
+impl MetronomeSettings {
+    pub fn is_valid(&self) -> bool {
+        let (numerator, denominator) = self.time_signature;
+
+        self.bpm.is_finite()
+            && (1.0..=1000.0).contains(&self.bpm)
+            && numerator > 0
+            && denominator.is_power_of_two()
+            && (1..=16).contains(&self.subdivision)
+            && self.accent_key < 128
+            && self.beat_key < 128
+    }
+
+    fn beat_length(&self) -> Duration {
+        let quarter = 60.0 / self.bpm;
+        Duration::from_secs_f64(quarter * 4.0 / self.time_signature.1 as f64)
+    }
+
+    fn click_length(&self) -> Duration {
+        self.beat_length() / self.subdivision as u32
+    }
+
+    /// The `index`th click since the metronome started, with the NoteOn to
+    /// play for it.
+    fn click(&self, index: u64) -> (MetronomeTickEvent, [u8; 3]) {
+        let subdivision = (index % self.subdivision as u64) as u8;
+        let beat_index = index / self.subdivision as u64;
+        let beat = (beat_index % self.time_signature.0 as u64) as u8;
+        let accent = subdivision == 0 && self.accents.contains(&beat);
+
+        let (key, velocity) = match (accent, subdivision) {
+            (true, _) => (self.accent_key, ACCENT_VELOCITY),
+            (false, 0) => (self.beat_key, BEAT_VELOCITY),
+            (false, _) => (self.beat_key, SUBDIVISION_VELOCITY),
+        };
+
+        let event = MetronomeTickEvent {
+            bar: beat_index / self.time_signature.0 as u64,
+            beat,
+            subdivision,
+            accent,
+        };
+        (event, [0x90 | CLICK_CHANNEL, key, velocity])
+    }
+}
+
+#[derive(Debug, Serialize, Clone, Copy)]
+pub struct MetronomeTickEvent {
+    /// Bar since the metronome started, counting from 0.
+    bar: u64,
+    beat: u8,
+    /// Click within the beat; 0 is on the beat itself.
+    subdivision: u8,
+    accent: bool,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(default)]
+pub struct CountInSettings {
+    pub bars: u32,
+    /// Whether the count-in clicks on the MIDI output, or only emits events.
+    pub audible: bool,
+}
+
+impl Default for CountInSettings {
+    fn default() -> Self {
+        CountInSettings {
+            bars: 1,
+            audible: true,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone, Copy)]
+pub struct CountInEvent {
+    bar: u32,
+    beat: u8,
+    /// Beats still to come; recording starts one beat after the event where
+    /// this is 0.
+    beats_remaining: u32,
+}
+
+/// Plays clicks on a thread of its own until stopped.
+pub struct Metronome {
+    settings: MetronomeSettings,
+    /// Time of the first downbeat.
+    start: Instant,
+    stopped: Arc<(Mutex<bool>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Metronome {
+    /// Starts clicking with the first downbeat at `start`, which may lie in
+    /// the future. Every MIDI message is passed to `output`, which is called
+    /// without any lock of the metronome held.
+    pub fn start(
+        app: AppHandle,
+        settings: MetronomeSettings,
+        start: Instant,
+        output: impl FnMut([u8; 3]) + Send + 'static,
+    ) -> Self {
+        Metronome::spawn(settings, start, None, output, move |event, _| {
+            app.emit("metronome_tick", event)
+                .expect("Error while emitting metronome tick");
+        })
+    }
+
+    /// Counts in `bars` bars starting at `start`, emitting a `count_in` event
+    /// for every beat. Returns the metronome playing the count-in and the
+    /// time it ends.
+    pub fn count_in(
+        app: AppHandle,
+        settings: MetronomeSettings,
+        bars: u32,
+        start: Instant,
+        output: impl FnMut([u8; 3]) + Send + 'static,
+    ) -> (Self, Instant) {
+        let settings = MetronomeSettings {
+            subdivision: 1,
+            ..settings
+        };
+        let beats = bars as u64 * settings.time_signature.0 as u64;
+        let end = start + settings.beat_length().mul_f64(beats as f64);
+
+        let metronome =
+            Metronome::spawn(settings, start, Some(beats), output, move |event, index| {
+                let event = CountInEvent {
+                    bar: event.bar as u32,
+                    beat: event.beat,
+                    beats_remaining: (beats - index - 1) as u32,
+                };
+                app.emit("count_in", event)
+                    .expect("Error while emitting count in");
+            });
+        (metronome, end)
+    }
+
+    /// Runs the clicks, calling `on_click` with each click and its index.
+    /// With a `limit`, the metronome stops by itself after that many clicks.
+    fn spawn(
+        settings: MetronomeSettings,
+        start: Instant,
+        limit: Option<u64>,
+        mut output: impl FnMut([u8; 3]) + Send + 'static,
+        mut on_click: impl FnMut(MetronomeTickEvent, u64) + Send + 'static,
+    ) -> Self {
+        let stopped = Arc::new((Mutex::new(false), Condvar::new()));
+        let thread_stopped = stopped.clone();
+        let thread_settings = settings.clone();
+
+        let thread = thread::spawn(move || {
+            let settings = thread_settings;
+            let (lock, wake) = &*thread_stopped;
+            let click_length = settings.click_length();
+            let mut index = 0u64;
+            let mut sounding: Option<[u8; 3]> = None;
+
+            loop {
+                let stopped = lock.lock().unwrap();
+                let now = Instant::now();
+                let due = start + click_length.mul_f64(index as f64);
+                if *stopped {
+                    break;
+                }
+                if due > now {
+                    drop(wake.wait_timeout(stopped, due - now).unwrap());
+                    continue;
+                }
+                drop(stopped);
+
+                // Skip clicks that were missed entirely, e.g. after a stall.
+                let elapsed = now.duration_since(start).as_secs_f64();
+                index = index.max((elapsed / click_length.as_secs_f64()) as u64);
+                if limit.is_some_and(|limit| index >= limit) {
+                    break;
+                }
+
+                if let Some(note_off) = sounding.take() {
+                    output(note_off);
+                }
+                let (event, note_on) = settings.click(index);
+                output(note_on);
+                sounding = Some([0x80 | CLICK_CHANNEL, note_on[1], 0]);
+
+                on_click(event, index);
+                index += 1;
+            }
+
+            if let Some(note_off) = sounding {
+                output(note_off);
+            }
+        });
+
+        Metronome {
+            settings,
+            start,
+            stopped,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn settings(&self) -> &MetronomeSettings {
+        &self.settings
+    }
+
+    /// The first downbeat of a bar at or after `time`.
+    pub fn next_downbeat(&self, time: Instant) -> Instant {
+        let bar_length = self.settings.beat_length() * self.settings.time_signature.0 as u32;
+        let elapsed = time.saturating_duration_since(self.start);
+        let bars = (elapsed.as_secs_f64() / bar_length.as_secs_f64()).ceil();
+        self.start + bar_length.mul_f64(bars)
+    }
+
+    /// Stops the metronome and waits for its last note to be released. The
+    /// output may lock the app state, so it must not be held while stopping.
+    pub fn stop(mut self) {
+        let (lock, wake) = &*self.stopped;
+        *lock.lock().unwrap() = true;
+        wake.notify_all();
+
+        if let Some(thread) = self.thread.take() {
+            thread.join().expect("Error while stopping metronome");
+        }
+    }
+}
@@ -0,0 +1,26 @@
+//! Many keyboards send NoteOn with velocity 0 instead of NoteOff, because it
+//! lets a whole chord be played and released under running status. Everything
+//! that reads MIDI goes through these functions so both conventions behave the
+//! same way.
+
+use midly::MidiMessage;
+
+use crate::{EventType, NoteState, Velocity};
+
+/// Rewrites a NoteOn with velocity 0 as the NoteOff it stands for.
+pub fn normalize_message(message: MidiMessage) -> MidiMessage {
+    match message {
+        MidiMessage::NoteOn { key, vel } if vel == 0 => MidiMessage::NoteOff { key, vel },
+        message => message,
+    }
+}
+
+/// Rewrites a NoteOn with velocity 0 as the NoteOff it stands for.
+pub fn normalize_event_type(event_type: EventType) -> EventType {
+    match event_type {
+        EventType::Note(NoteState::On, key, Velocity(0)) => {
+            EventType::Note(NoteState::Off, key, Velocity(0))
+        }
+        event_type => event_type,
+    }
+}
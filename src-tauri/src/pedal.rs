@@ -0,0 +1,313 @@
+use std::{collections::HashSet, mem};
+
+use serde::Serialize;
+
+use crate::{Channel, ControlValue, Controller, EventType, NoteState, PianoEvent, PianoKeyCode};
+
+const DAMPER_CONTROLLER: u8 = 64;
+const SOSTENUTO_CONTROLLER: u8 = 66;
+const SOFT_CONTROLLER: u8 = 67;
+
+/// Damper values below this lift no dampers at all.
+const HALF_PEDAL_THRESHOLD: u8 = 32;
+/// Damper values at or above this lift the dampers completely.
+const FULL_PEDAL_THRESHOLD: u8 = 96;
+/// Sostenuto and soft pedals are switches, using the usual MIDI on/off split.
+const SWITCH_THRESHOLD: u8 = 64;
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum Pedal {
+    Damper,
+    Sostenuto,
+    Soft,
+}
+
+impl Pedal {
+    fn from_controller(controller: u8) -> Option<Self> {
+        match controller {
+            DAMPER_CONTROLLER => Some(Pedal::Damper),
+            SOSTENUTO_CONTROLLER => Some(Pedal::Sostenuto),
+            SOFT_CONTROLLER => Some(Pedal::Soft),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum PedalPosition {
+    Up,
+    Half,
+    Down,
+}
+
+impl PedalPosition {
+    fn from_value(pedal: Pedal, value: u8) -> Self {
+        match pedal {
+            Pedal::Damper if value >= FULL_PEDAL_THRESHOLD => PedalPosition::Down,
+            Pedal::Damper if value >= HALF_PEDAL_THRESHOLD => PedalPosition::Half,
+            Pedal::Sostenuto | Pedal::Soft if value >= SWITCH_THRESHOLD => PedalPosition::Down,
+            _ => PedalPosition::Up,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone, Copy)]
+pub struct PedalEvent {
+    pedal: Pedal,
+    channel: Channel,
+    value: u8,
+    /// How far the pedal is pressed, from 0.0 (up) to 1.0 (fully down).
+    depth: f32,
+    position: PedalPosition,
+}
+
+/// Emitted when a note stops sounding, which may be long after its key was
+/// released if a pedal was holding it.
+#[derive(Debug, Serialize, Clone, Copy)]
+pub struct NoteReleasedEvent {
+    key: PianoKeyCode,
+    channel: Channel,
+}
+
+#[derive(Debug)]
+pub enum PedalOutput {
+    Pedal(PedalEvent),
+    NoteReleased(NoteReleasedEvent),
+}
+
+#[derive(Debug, Default)]
+struct ChannelPedals {
+    damper: u8,
+    sostenuto: u8,
+    soft: u8,
+    /// Keys that are physically held down.
+    held: HashSet<PianoKeyCode>,
+    /// Keys that have been released but are still sounding.
+    sustained: HashSet<PianoKeyCode>,
+    /// Keys caught by the sostenuto pedal when it was pressed.
+    sostenuto_latched: HashSet<PianoKeyCode>,
+}
+
+impl ChannelPedals {
+    fn damper_lifted(&self) -> bool {
+        PedalPosition::from_value(Pedal::Damper, self.damper) != PedalPosition::Up
+    }
+
+    fn holds(&self, key: PianoKeyCode) -> bool {
+        self.damper_lifted() || self.sostenuto_latched.contains(&key)
+    }
+
+    fn press(&mut self, key: PianoKeyCode) {
+        self.held.insert(key);
+        // Restriking a sustained key keeps it sounding.
+        self.sustained.remove(&key);
+    }
+
+    /// Returns the key if releasing it also stops it from sounding.
+    fn release(&mut self, key: PianoKeyCode) -> Option<PianoKeyCode> {
+        if !self.held.remove(&key) {
+            return None;
+        }
+        if self.holds(key) {
+            self.sustained.insert(key);
+            return None;
+        }
+        Some(key)
+    }
+
+    /// Moves a pedal to a new value, returning its new position and any keys
+    /// that stopped sounding because of it. Returns `None` if nothing changed.
+    fn set_pedal(&mut self, pedal: Pedal, value: u8) -> Option<(PedalPosition, Vec<PianoKeyCode>)> {
+        let previous = match pedal {
+            Pedal::Damper => mem::replace(&mut self.damper, value),
+            Pedal::Sostenuto => mem::replace(&mut self.sostenuto, value),
+            Pedal::Soft => mem::replace(&mut self.soft, value),
+        };
+        if previous == value {
+            return None;
+        }
+
+        let was_down = PedalPosition::from_value(pedal, previous) != PedalPosition::Up;
+        let position = PedalPosition::from_value(pedal, value);
+        let is_down = position != PedalPosition::Up;
+
+        let released = match pedal {
+            Pedal::Sostenuto if is_down && !was_down => {
+                self.sostenuto_latched = self.held.clone();
+                vec![]
+            }
+            Pedal::Sostenuto if !is_down && was_down => {
+                self.sostenuto_latched.clear();
+                self.release_unheld()
+            }
+            Pedal::Damper if !is_down && was_down => self.release_unheld(),
+            _ => vec![],
+        };
+
+        Some((position, released))
+    }
+
+    /// Releases every sustained key that is no longer held by a pedal.
+    fn release_unheld(&mut self) -> Vec<PianoKeyCode> {
+        let released = self
+            .sustained
+            .iter()
+            .copied()
+            .filter(|key| !self.holds(*key))
+            .collect::<Vec<_>>();
+
+        for key in &released {
+            self.sustained.remove(key);
+        }
+
+        released
+    }
+}
+
+/// Tracks pedal positions and which notes are still sounding on every channel.
+#[derive(Debug, Default)]
+pub struct PedalState {
+    channels: [ChannelPedals; 16],
+}
+
+impl PedalState {
+    pub fn handle(&mut self, piano_event: &PianoEvent) -> Vec<PedalOutput> {
+        let channel = piano_event.channel;
+        let pedals = &mut self.channels[channel as usize];
+        let mut outputs = vec![];
+
+        let released = match piano_event.event_type {
+            EventType::Note(NoteState::On, key, _) => {
+                pedals.press(key);
+                vec![]
+            }
+            EventType::Note(NoteState::Off, key, _) => pedals.release(key).into_iter().collect(),
+            EventType::ControlChange(Controller(controller), ControlValue(value)) => {
+                let Some(pedal) = Pedal::from_controller(controller) else {
+                    return outputs;
+                };
+                let Some((position, released)) = pedals.set_pedal(pedal, value) else {
+                    return outputs;
+                };
+
+                outputs.push(PedalOutput::Pedal(PedalEvent {
+                    pedal,
+                    channel,
+                    value,
+                    depth: value as f32 / 127.0,
+                    position,
+                }));
+                released
+            }
+            _ => vec![],
+        };
+
+        outputs.extend(
+            released
+                .into_iter()
+                .map(|key| PedalOutput::NoteReleased(NoteReleasedEvent { key, channel })),
+        );
+
+        outputs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(key: u8) -> PianoKeyCode {
+        PianoKeyCode::try_from(key).unwrap()
+    }
+
+    #[test]
+    fn damper_holds_released_notes_until_pedal_up() {
+        let mut pedals = ChannelPedals::default();
+        pedals.set_pedal(Pedal::Damper, 127);
+        pedals.press(key(60));
+
+        assert_eq!(pedals.release(key(60)), None);
+        assert_eq!(
+            pedals.set_pedal(Pedal::Damper, 0),
+            Some((PedalPosition::Up, vec![key(60)]))
+        );
+        // Notes released with the pedal up stop at once.
+        pedals.press(key(62));
+        assert_eq!(pedals.release(key(62)), Some(key(62)));
+    }
+
+    #[test]
+    fn keys_still_held_at_pedal_up_keep_sounding() {
+        let mut pedals = ChannelPedals::default();
+        pedals.set_pedal(Pedal::Damper, 127);
+        pedals.press(key(60));
+
+        assert_eq!(
+            pedals.set_pedal(Pedal::Damper, 0),
+            Some((PedalPosition::Up, vec![]))
+        );
+        assert_eq!(pedals.release(key(60)), Some(key(60)));
+    }
+
+    #[test]
+    fn sostenuto_latches_only_keys_held_when_pressed() {
+        let mut pedals = ChannelPedals::default();
+        pedals.press(key(60));
+        pedals.set_pedal(Pedal::Sostenuto, 127);
+        pedals.press(key(64));
+
+        assert_eq!(pedals.release(key(60)), None);
+        assert_eq!(pedals.release(key(64)), Some(key(64)));
+        assert_eq!(
+            pedals.set_pedal(Pedal::Sostenuto, 0),
+            Some((PedalPosition::Up, vec![key(60)]))
+        );
+    }
+
+    #[test]
+    fn damper_has_half_pedal_positions() {
+        let position = |value| PedalPosition::from_value(Pedal::Damper, value);
+
+        assert_eq!(position(HALF_PEDAL_THRESHOLD - 1), PedalPosition::Up);
+        assert_eq!(position(HALF_PEDAL_THRESHOLD), PedalPosition::Half);
+        assert_eq!(position(FULL_PEDAL_THRESHOLD - 1), PedalPosition::Half);
+        assert_eq!(position(FULL_PEDAL_THRESHOLD), PedalPosition::Down);
+    }
+
+    #[test]
+    fn half_pedal_sustains_like_full_pedal() {
+        let mut pedals = ChannelPedals::default();
+        pedals.set_pedal(Pedal::Damper, HALF_PEDAL_THRESHOLD);
+        pedals.press(key(60));
+
+        assert_eq!(pedals.release(key(60)), None);
+        assert_eq!(
+            pedals.set_pedal(Pedal::Damper, FULL_PEDAL_THRESHOLD),
+            Some((PedalPosition::Down, vec![]))
+        );
+        assert_eq!(
+            pedals.set_pedal(Pedal::Damper, HALF_PEDAL_THRESHOLD - 1),
+            Some((PedalPosition::Up, vec![key(60)]))
+        );
+    }
+
+    #[test]
+    fn switch_pedals_split_at_64() {
+        assert_eq!(
+            PedalPosition::from_value(Pedal::Sostenuto, SWITCH_THRESHOLD - 1),
+            PedalPosition::Up
+        );
+        assert_eq!(
+            PedalPosition::from_value(Pedal::Soft, SWITCH_THRESHOLD),
+            PedalPosition::Down
+        );
+    }
+
+    #[test]
+    fn unchanged_pedal_value_reports_nothing() {
+        let mut pedals = ChannelPedals::default();
+        pedals.set_pedal(Pedal::Soft, 127);
+
+        assert_eq!(pedals.set_pedal(Pedal::Soft, 127), None);
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+
+use midly::{live::LiveEvent, num::u4, MetaMessage, MidiMessage, Smf, TrackEventKind};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    normalize::normalize_message,
+    tempo::{bar_ticks, TempoMap},
+};
+
+pub struct PlaybackEvent {
+    /// Time since the start of the song, in microseconds.
+    pub time: u64,
+    /// Index of the track in the MIDI file the event came from.
+    pub track: usize,
+    /// Raw MIDI bytes, zero-padded for two-byte messages.
+    pub message: [u8; 3],
+    /// Length of the note in milliseconds, for NoteOn events.
+    pub time_length: u32,
+    pub is_note_on: bool,
+}
+
+impl PlaybackEvent {
+    /// Packs the event for the frontend, with the bytes actually being played
+    /// and the note length scaled to the playback speed.
+    pub fn to_packed(&self, message: [u8; 3], speed: f64) -> PackedPlaybackEvent {
+        PackedPlaybackEvent {
+            track: self.track,
+            is_note_on: self.is_note_on,
+            message,
+            time_length: (self.time_length as f64 / speed) as u32,
+        }
+    }
+
+    fn from_midi(time: u64, track: usize, channel: u4, message: MidiMessage) -> Self {
+        let event = LiveEvent::Midi { channel, message };
+
+        let is_note_on = matches!(message, MidiMessage::NoteOn { .. });
+
+        // Create a buffer to hold the raw MIDI bytes
+        let mut buf = Vec::with_capacity(3);
+
+        // Write the event's raw bytes into the buffer
+        event.write(&mut buf).expect("Failed to write MIDI event");
+        buf.resize(3, 0);
+
+        PlaybackEvent {
+            time,
+            track,
+            message: [buf[0], buf[1], buf[2]],
+            is_note_on,
+            time_length: 0,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PackedPlaybackEvent {
+    track: usize,
+    is_note_on: bool,
+    message: [u8; 3],
+    time_length: u32,
+}
+
+/// Which tracks of a MIDI file take part in playback.
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct TrackSelection {
+    /// Tracks to play at all; every track when `None`.
+    pub play: Option<Vec<usize>>,
+    /// Tracks shown as falling notes; every played track when `None`.
+    pub display: Option<Vec<usize>>,
+    /// Tracks that are shown but not sent to the MIDI output.
+    pub mute: Vec<usize>,
+}
+
+impl TrackSelection {
+    pub fn is_played(&self, track: usize) -> bool {
+        self.play.as_ref().is_none_or(|play| play.contains(&track))
+    }
+
+    pub fn is_displayed(&self, track: usize) -> bool {
+        self.is_played(track)
+            && self
+                .display
+                .as_ref()
+                .is_none_or(|display| display.contains(&track))
+    }
+
+    pub fn is_audible(&self, track: usize) -> bool {
+        self.is_played(track) && !self.mute.contains(&track)
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct MidiTrackInfo {
+    index: usize,
+    name: Option<String>,
+    note_count: usize,
+    channels: Vec<u8>,
+}
+
+pub fn track_info(smf: &Smf) -> Vec<MidiTrackInfo> {
+    smf.tracks
+        .iter()
+        .enumerate()
+        .map(|(index, track)| {
+            let mut name = None;
+            let mut note_count = 0;
+            let mut channels = Vec::new();
+
+            for event in track {
+                match event.kind {
+                    TrackEventKind::Meta(MetaMessage::TrackName(bytes)) if name.is_none() => {
+                        name = Some(String::from_utf8_lossy(bytes).trim().to_string());
+                    }
+                    TrackEventKind::Midi { channel, message } => {
+                        if let MidiMessage::NoteOn { .. } = normalize_message(message) {
+                            note_count += 1;
+                        }
+                        if !channels.contains(&channel.as_int()) {
+                            channels.push(channel.as_int());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            channels.sort();
+
+            MidiTrackInfo {
+                index,
+                name,
+                note_count,
+                channels,
+            }
+        })
+        .collect()
+}
+
+/// Everything the playback engine needs to know about a MIDI file.
+pub struct Song {
+    pub timeline: Vec<PlaybackEvent>,
+    /// Start time of every bar in microseconds; bar 1 is at index 0.
+    pub bars: Vec<u64>,
+}
+
+impl Song {
+    pub fn from_smf(smf: &Smf) -> Self {
+        let tempo_map = TempoMap::from_smf(smf);
+
+        Song {
+            timeline: build_timeline(smf),
+            bars: bar_ticks(smf)
+                .into_iter()
+                .map(|tick| tempo_map.tick_to_micros(tick))
+                .collect(),
+        }
+    }
+}
+
+/// Builds the time-ordered list of MIDI events to play, with every event
+/// placed at its real time according to the file's tempo map.
+///
+/// Events from all tracks are merged into one stream. Events at the same tick
+/// keep the order of their track index, then their order within the track.
+pub fn build_timeline(smf: &Smf) -> Vec<PlaybackEvent> {
+    let tempo_map = TempoMap::from_smf(smf);
+
+    let mut midi_events = Vec::new();
+    for (track, events) in smf.tracks.iter().enumerate() {
+        let mut tick = 0u64;
+        for event in events {
+            tick += event.delta.as_int() as u64;
+            if let TrackEventKind::Midi { channel, message } = event.kind {
+                midi_events.push((tick, track, channel, message));
+            }
+        }
+    }
+    midi_events.sort_by_key(|(tick, ..)| *tick);
+
+    // (track, channel, key) to (start time, index in playback_track)
+    let mut events_map: HashMap<(usize, u8, u8), (u64, usize)> = HashMap::new();
+    let mut playback_track: Vec<PlaybackEvent> = Vec::with_capacity(midi_events.len());
+
+    for (tick, track, channel, message) in midi_events {
+        let time = tempo_map.tick_to_micros(tick);
+        let message = normalize_message(message);
+        playback_track.push(PlaybackEvent::from_midi(time, track, channel, message));
+
+        match message {
+            MidiMessage::NoteOn { key, .. } => {
+                let note = (track, channel.as_int(), key.as_int());
+                events_map.insert(note, (time, playback_track.len() - 1));
+            }
+            MidiMessage::NoteOff { key, .. } => {
+                let note = (track, channel.as_int(), key.as_int());
+                if let Some((start_time, index)) = events_map.remove(&note) {
+                    playback_track[index].time_length = ((time - start_time) / 1000) as u32;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    playback_track
+}
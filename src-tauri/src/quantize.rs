@@ -0,0 +1,425 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use midly::{num::u28, MetaMessage, MidiMessage, Smf, Timing, Track, TrackEvent, TrackEventKind};
+use serde::{Deserialize, Serialize};
+
+use crate::normalize::normalize_message;
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(default)]
+pub struct QuantizeSettings {
+    /// Grid as a note value: 4 for quarter notes up to 32 for 32nd notes.
+    pub grid: u8,
+    /// Divides every grid note into triplets.
+    pub triplets: bool,
+    /// How far notes move towards the grid, from 0 to 100 percent.
+    pub strength: f64,
+    /// Delays every second grid line, from 0 (straight) to 100 percent, which
+    /// moves it a third of a grid step later, a triplet shuffle.
+    pub swing: f64,
+    /// Notes further from the grid than this percentage of half a grid step
+    /// are considered expressive and left untouched.
+    pub window: f64,
+    /// Whether note ends are snapped too; otherwise notes keep their length.
+    pub quantize_ends: bool,
+}
+
+impl Default for QuantizeSettings {
+    fn default() -> Self {
+        QuantizeSettings {
+            grid: 16,
+            triplets: false,
+            strength: 100.0,
+            swing: 0.0,
+            window: 100.0,
+            quantize_ends: false,
+        }
+    }
+}
+
+impl QuantizeSettings {
+    pub fn is_valid(&self) -> bool {
+        let is_percentage = |value: f64| (0.0..=100.0).contains(&value);
+
+        matches!(self.grid, 4 | 8 | 16 | 32)
+            && is_percentage(self.strength)
+            && is_percentage(self.swing)
+            && is_percentage(self.window)
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+pub enum QuantizeError {
+    InvalidSettings,
+    /// The file could not be read, parsed or written.
+    Io(String),
+    /// Timecode files have no beats to quantize to.
+    UnsupportedTiming,
+}
+
+impl std::fmt::Display for QuantizeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QuantizeError::InvalidSettings => write!(f, "invalid quantize settings"),
+            QuantizeError::Io(err) => write!(f, "error while quantizing file: {}", err),
+            QuantizeError::UnsupportedTiming => write!(f, "timecode files cannot be quantized"),
+        }
+    }
+}
+
+impl std::error::Error for QuantizeError {}
+
+impl From<std::io::Error> for QuantizeError {
+    fn from(err: std::io::Error) -> Self {
+        QuantizeError::Io(err.to_string())
+    }
+}
+
+struct Grid {
+    /// Length of a grid step in ticks.
+    step: f64,
+    /// Delay of every odd grid line in ticks.
+    swing: f64,
+}
+
+impl Grid {
+    fn new(ticks_per_quarter: u64, settings: &QuantizeSettings) -> Self {
+        let mut step = ticks_per_quarter as f64 * 4.0 / settings.grid as f64;
+        if settings.triplets {
+            step = step * 2.0 / 3.0;
+        }
+
+        Grid {
+            step,
+            swing: step / 3.0 * settings.swing / 100.0,
+        }
+    }
+
+    fn line(&self, index: i64) -> f64 {
+        let swing = if index.rem_euclid(2) == 1 {
+            self.swing
+        } else {
+            0.0
+        };
+        index as f64 * self.step + swing
+    }
+
+    /// The grid line closest to `tick`.
+    fn nearest(&self, tick: u64) -> f64 {
+        let index = (tick as f64 / self.step).floor() as i64;
+        (index - 1..=index + 1)
+            .map(|index| self.line(index))
+            .min_by(|a, b| (a - tick as f64).abs().total_cmp(&(b - tick as f64).abs()))
+            .unwrap()
+    }
+}
+
+/// Moves note starts, and optionally ends, towards the grid. Every other
+/// event keeps its tick.
+pub fn quantize<'a>(smf: &Smf<'a>, settings: &QuantizeSettings) -> Result<Smf<'a>, QuantizeError> {
+    if !settings.is_valid() {
+        return Err(QuantizeError::InvalidSettings);
+    }
+    let Timing::Metrical(ticks_per_quarter) = smf.header.timing else {
+        return Err(QuantizeError::UnsupportedTiming);
+    };
+    let grid = Grid::new(ticks_per_quarter.as_int().max(1) as u64, settings);
+    let window = grid.step / 2.0 * settings.window / 100.0;
+
+    let snap = |tick: u64| {
+        let line = grid.nearest(tick);
+        let distance = line - tick as f64;
+        if distance.abs() > window {
+            return tick;
+        }
+        (tick as f64 + distance * settings.strength / 100.0)
+            .round()
+            .max(0.0) as u64
+    };
+
+    let mut quantized = Smf::new(smf.header);
+    for track in &smf.tracks {
+        let mut tick = 0u64;
+        let mut events = track
+            .iter()
+            .map(|event| {
+                tick += event.delta.as_int() as u64;
+                (tick, event.kind)
+            })
+            .filter(|(_, kind)| !matches!(kind, TrackEventKind::Meta(MetaMessage::EndOfTrack)))
+            .collect::<Vec<_>>();
+        let end_tick = tick;
+
+        // (channel, key) to (original start, new start) of sounding notes
+        let mut notes: HashMap<(u8, u8), (u64, u64)> = HashMap::new();
+        // New starts of the notes on every (channel, key)
+        let mut starts: HashMap<(u8, u8), Vec<u64>> = HashMap::new();
+        // (channel, key), new start and index of the NoteOff of every note
+        let mut ends = vec![];
+        for (index, (tick, kind)) in events.iter_mut().enumerate() {
+            let TrackEventKind::Midi { channel, message } = *kind else {
+                continue;
+            };
+            match normalize_message(message) {
+                MidiMessage::NoteOn { key, .. } => {
+                    let note = (channel.as_int(), key.as_int());
+                    let start = snap(*tick);
+                    notes.insert(note, (*tick, start));
+                    starts.entry(note).or_default().push(start);
+                    *tick = start;
+                }
+                MidiMessage::NoteOff { key, .. } => {
+                    let note = (channel.as_int(), key.as_int());
+                    let Some((start, new_start)) = notes.remove(&note) else {
+                        continue;
+                    };
+                    *tick = if settings.quantize_ends {
+                        snap(*tick)
+                    } else {
+                        (*tick + new_start).saturating_sub(start)
+                    };
+                    ends.push((note, new_start, index));
+                }
+                _ => {}
+            }
+        }
+
+        // A note moved before the end of the previous one on its key would be
+        // cut short by that end, so ends are pulled back to the next start.
+        for starts in starts.values_mut() {
+            starts.sort();
+        }
+        for (note, start, index) in ends {
+            let note_starts = &starts[&note];
+            let next_start = note_starts
+                .get(note_starts.partition_point(|other| *other <= start))
+                .copied()
+                .unwrap_or(u64::MAX);
+            let tick = &mut events[index].0;
+            // Keep at least a tick between the start and the end.
+            *tick = (*tick).min(next_start).max(start + 1);
+        }
+
+        // Stable, so events that end up on the same tick keep their order,
+        // except that note ends go first so they never end a note starting
+        // on the same tick.
+        events.sort_by_key(|(tick, kind)| {
+            let is_note_off = matches!(
+                kind,
+                TrackEventKind::Midi { message, .. }
+                    if matches!(normalize_message(*message), MidiMessage::NoteOff { .. })
+            );
+            (*tick, !is_note_off)
+        });
+
+        // Notes moved later may pass the original end of the track.
+        let end_tick = events
+            .last()
+            .map_or(end_tick, |(tick, _)| end_tick.max(*tick));
+        events.push((end_tick, TrackEventKind::Meta(MetaMessage::EndOfTrack)));
+
+        let mut last_tick = 0;
+        let track: Track = events
+            .into_iter()
+            .map(|(tick, kind)| {
+                let delta = tick - last_tick;
+                last_tick = tick;
+                TrackEvent {
+                    delta: u28::from_int_lossy(delta as u32),
+                    kind,
+                }
+            })
+            .collect();
+        quantized.tracks.push(track);
+    }
+
+    Ok(quantized)
+}
+
+/// Path for the quantized copy of `path`, next to it, e.g.
+/// `take-quantized.mid`, numbered if that file already exists.
+pub fn quantized_path(path: &Path) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map_or("recording".into(), |stem| stem.to_string_lossy());
+
+    let mut quantized = path.with_file_name(format!("{}-quantized.mid", stem));
+    let mut count = 1;
+    while quantized.exists() {
+        count += 1;
+        quantized = path.with_file_name(format!("{}-quantized-{}.mid", stem, count));
+    }
+    quantized
+}
+
+#[cfg(test)]
+mod tests {
+    use midly::{
+        num::{u15, u4, u7},
+        Format, Fps, Header,
+    };
+
+    use super::*;
+
+    /// A single-track file of notes on key 60, as (start, end) ticks.
+    fn smf(notes: &[(u64, u64)]) -> Smf<'static> {
+        let mut events = vec![];
+        for (start, end) in notes {
+            events.push((*start, true));
+            events.push((*end, false));
+        }
+        events.sort_by_key(|(tick, _)| *tick);
+
+        let mut last = 0;
+        let mut track = vec![];
+        for (tick, is_note_on) in events {
+            let key = u7::new(60);
+            let message = if is_note_on {
+                MidiMessage::NoteOn {
+                    key,
+                    vel: u7::new(100),
+                }
+            } else {
+                MidiMessage::NoteOff {
+                    key,
+                    vel: u7::new(0),
+                }
+            };
+            track.push(TrackEvent {
+                delta: u28::new((tick - last) as u32),
+                kind: TrackEventKind::Midi {
+                    channel: u4::new(0),
+                    message,
+                },
+            });
+            last = tick;
+        }
+        track.push(TrackEvent {
+            delta: u28::new(0),
+            kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+        });
+
+        let timing = Timing::Metrical(u15::new(480));
+        let mut smf = Smf::new(Header::new(Format::SingleTrack, timing));
+        smf.tracks.push(track);
+        smf
+    }
+
+    /// Every note event of the first track as (tick, is NoteOn).
+    fn note_events(smf: &Smf) -> Vec<(u64, bool)> {
+        let mut tick = 0;
+        smf.tracks[0]
+            .iter()
+            .filter_map(|event| {
+                tick += event.delta.as_int() as u64;
+                match event.kind {
+                    TrackEventKind::Midi { message, .. } => {
+                        Some((tick, matches!(message, MidiMessage::NoteOn { .. })))
+                    }
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    fn quantized(notes: &[(u64, u64)], settings: QuantizeSettings) -> Vec<(u64, bool)> {
+        note_events(&quantize(&smf(notes), &settings).unwrap())
+    }
+
+    #[test]
+    fn snaps_starts_and_keeps_lengths() {
+        assert_eq!(
+            quantized(&[(130, 250)], QuantizeSettings::default()),
+            vec![(120, true), (240, false)]
+        );
+    }
+
+    #[test]
+    fn snaps_ends_when_asked() {
+        let settings = QuantizeSettings {
+            quantize_ends: true,
+            ..QuantizeSettings::default()
+        };
+
+        assert_eq!(
+            quantized(&[(130, 250)], settings),
+            vec![(120, true), (240, false)]
+        );
+        assert_eq!(
+            quantized(&[(130, 310)], settings),
+            vec![(120, true), (360, false)]
+        );
+    }
+
+    #[test]
+    fn moves_notes_part_of_the_way_with_less_strength() {
+        let settings = QuantizeSettings {
+            strength: 50.0,
+            ..QuantizeSettings::default()
+        };
+
+        assert_eq!(
+            quantized(&[(130, 250)], settings),
+            vec![(125, true), (245, false)]
+        );
+    }
+
+    #[test]
+    fn leaves_notes_outside_the_window() {
+        let settings = QuantizeSettings {
+            window: 50.0,
+            ..QuantizeSettings::default()
+        };
+
+        assert_eq!(
+            quantized(&[(170, 200)], settings),
+            vec![(170, true), (200, false)]
+        );
+    }
+
+    #[test]
+    fn swing_delays_every_second_grid_line() {
+        let settings = QuantizeSettings {
+            swing: 100.0,
+            ..QuantizeSettings::default()
+        };
+
+        // The second sixteenth moves from 120 to 160.
+        assert_eq!(
+            quantized(&[(150, 200)], settings),
+            vec![(160, true), (210, false)]
+        );
+    }
+
+    #[test]
+    fn notes_moved_earlier_end_the_previous_note_first() {
+        // The second note moves from 130 to 120, before the first one ends.
+        assert_eq!(
+            quantized(&[(0, 125), (130, 250)], QuantizeSettings::default()),
+            vec![(0, true), (120, false), (120, true), (240, false)]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_settings_and_timecode() {
+        let settings = QuantizeSettings {
+            grid: 3,
+            ..QuantizeSettings::default()
+        };
+        assert!(matches!(
+            quantize(&smf(&[]), &settings),
+            Err(QuantizeError::InvalidSettings)
+        ));
+
+        let mut timecode = smf(&[]);
+        timecode.header.timing = Timing::Timecode(Fps::Fps25, 40);
+        assert!(matches!(
+            quantize(&timecode, &QuantizeSettings::default()),
+            Err(QuantizeError::UnsupportedTiming)
+        ));
+    }
+}
@@ -0,0 +1,697 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use midly::{
+    num::{u15, u24, u28, u4, u7},
+    Format, Header, MetaMessage, MidiMessage, Smf, Timing, Track, TrackEvent, TrackEventKind,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    engine::{transpose_key, PlaybackClock},
+    library::LibraryError,
+    tempo::TempoMap,
+    AvailableMidiInput,
+};
+
+/// Name of the track metronome clicks are recorded to.
+const CLICK_TRACK_NAME: &[u8] = b"Metronome";
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(default)]
+pub struct RecordingSettings {
+    pub bpm: f64,
+    /// Ticks per quarter note written to the file header.
+    pub ppq: u16,
+    /// Time signature as (numerator, denominator), e.g. (6, 8).
+    pub time_signature: (u8, u8),
+    /// Whether metronome clicks are written to a track of their own.
+    pub click_track: bool,
+    /// Whether each MIDI input is recorded to a track of its own, named after
+    /// the device; otherwise all inputs are merged into one track.
+    pub separate_inputs: bool,
+}
+
+impl Default for RecordingSettings {
+    fn default() -> Self {
+        RecordingSettings {
+            bpm: 120.0,
+            ppq: 480,
+            time_signature: (4, 4),
+            click_track: false,
+            separate_inputs: false,
+        }
+    }
+}
+
+impl RecordingSettings {
+    pub fn is_valid(&self) -> bool {
+        let (numerator, denominator) = self.time_signature;
+
+        self.bpm.is_finite()
+            && (1.0..=1000.0).contains(&self.bpm)
+            // Below about 3.6 bpm, a quarter note is too long for the Tempo
+            // event.
+            && self.micros_per_quarter() <= u24::max_value().as_int()
+            && (1..=u15::max_value().as_int()).contains(&self.ppq)
+            && numerator > 0
+            && denominator.is_power_of_two()
+    }
+
+    /// Length of a quarter note in microseconds, as stored in the Tempo event.
+    pub fn micros_per_quarter(&self) -> u32 {
+        (60_000_000.0 / self.bpm).round() as u32
+    }
+
+    /// Converts a duration in microseconds to ticks at this tempo.
+    pub fn micros_to_ticks(&self, micros: u64) -> u64 {
+        let micros_per_quarter = self.micros_per_quarter() as u64;
+        (micros * self.ppq as u64 + micros_per_quarter / 2) / micros_per_quarter
+    }
+}
+
+/// Where a recording takes its time from.
+enum RecordingClock {
+    /// Time since recording started, at the tempo of the recording settings.
+    Wall {
+        start: Instant,
+        settings: RecordingSettings,
+    },
+    /// Song position of the playback being recorded over, on the tempo map of
+    /// the file being played.
+    Playback {
+        clock: PlaybackClock,
+        tempo_map: TempoMap,
+    },
+}
+
+impl RecordingClock {
+    /// Whether the first tick has passed; a wall clock starts in the future
+    /// while counting in.
+    fn has_started(&self) -> bool {
+        match self {
+            RecordingClock::Wall { start, .. } => Instant::now() >= *start,
+            RecordingClock::Playback { .. } => true,
+        }
+    }
+
+    /// Transposition of the playback being recorded over.
+    fn transpose(&self) -> i8 {
+        match self {
+            RecordingClock::Wall { .. } => 0,
+            RecordingClock::Playback { clock, .. } => clock.transpose(),
+        }
+    }
+
+    fn tick(&self) -> u64 {
+        match self {
+            RecordingClock::Wall { start, settings } => {
+                // Events before the start, during a count-in, land on tick 0.
+                let elapsed = Instant::now().saturating_duration_since(*start);
+                settings.micros_to_ticks(elapsed.as_micros() as u64)
+            }
+            RecordingClock::Playback { clock, tempo_map } => {
+                tempo_map.micros_to_tick(clock.position())
+            }
+        }
+    }
+}
+
+/// Events recorded from one input, when inputs are recorded separately.
+struct InputTrack {
+    /// Port id of the input.
+    index: String,
+    name: String,
+    events: Vec<(u64, TrackEventKind<'static>)>,
+}
+
+/// Records live MIDI into a new track, converting the time of each event
+/// into ticks.
+///
+/// A plain recording runs on its own clock at the tempo chosen when it
+/// started. An overdub follows the playback it is recorded over, and writes
+/// the tracks of the played file followed by the new one. Inputs recorded
+/// separately get a track each, after the tempo track of a plain recording.
+pub struct Recorder {
+    clock: RecordingClock,
+    header: Header,
+    /// Tracks of the file being overdubbed, written before the recorded one.
+    tracks: Vec<Track<'static>>,
+    /// Recorded events with their absolute tick. Seeking during an overdub
+    /// can move the clock backwards, so they are sorted when finishing.
+    events: Vec<(u64, TrackEventKind<'static>)>,
+    /// Tracks of each input, in the order they were first heard, when inputs
+    /// are recorded separately.
+    inputs: Option<Vec<InputTrack>>,
+    /// Metronome clicks, when they are recorded.
+    clicks: Option<Vec<(u64, TrackEventKind<'static>)>>,
+}
+
+impl Recorder {
+    /// Starts a recording whose first tick is at `start`, which lies in the
+    /// future when counting in.
+    pub fn new(settings: RecordingSettings, start: Instant) -> Self {
+        let (numerator, denominator) = settings.time_signature;
+
+        let events = vec![
+            (
+                0,
+                TrackEventKind::Meta(MetaMessage::Tempo(u24::from_int_lossy(
+                    settings.micros_per_quarter(),
+                ))),
+            ),
+            (
+                0,
+                TrackEventKind::Meta(MetaMessage::TimeSignature(
+                    numerator,
+                    denominator.trailing_zeros() as u8,
+                    24,
+                    8,
+                )),
+            ),
+        ];
+
+        Recorder {
+            clock: RecordingClock::Wall { start, settings },
+            header: Header {
+                format: Format::SingleTrack,
+                timing: Timing::Metrical(u15::new(settings.ppq)),
+            },
+            tracks: vec![],
+            events,
+            inputs: settings.separate_inputs.then(Vec::new),
+            clicks: settings.click_track.then(Vec::new),
+        }
+    }
+
+    /// Records over the playback of `smf`, following the song position of
+    /// `clock`. The tempo of `settings` is ignored in favour of the file's.
+    pub fn overdub(smf: Smf<'static>, clock: PlaybackClock, settings: &RecordingSettings) -> Self {
+        let events = if settings.separate_inputs {
+            vec![]
+        } else {
+            vec![(0, TrackEventKind::Meta(MetaMessage::TrackName(b"Overdub")))]
+        };
+
+        Recorder {
+            clock: RecordingClock::Playback {
+                clock,
+                tempo_map: TempoMap::from_smf(&smf),
+            },
+            header: Header {
+                format: Format::Parallel,
+                timing: smf.header.timing,
+            },
+            tracks: smf.tracks,
+            events,
+            inputs: settings.separate_inputs.then(Vec::new),
+            clicks: settings.click_track.then(Vec::new),
+        }
+    }
+
+    pub fn record(&mut self, source: &AvailableMidiInput, channel: u4, message: MidiMessage) {
+        // An overdub is saved with the tracks of the file, so what is played
+        // along to transposed playback is put back into the key of the file.
+        let Some(message) = untranspose(channel, message, self.clock.transpose()) else {
+            return;
+        };
+        let event = (self.clock.tick(), TrackEventKind::Midi { channel, message });
+        let Some(inputs) = &mut self.inputs else {
+            self.events.push(event);
+            return;
+        };
+
+        match inputs.iter_mut().find(|input| input.index == source.index) {
+            Some(input) => input.events.push(event),
+            None => inputs.push(InputTrack {
+                index: source.index.clone(),
+                name: source.name.clone(),
+                events: vec![event],
+            }),
+        }
+    }
+
+    /// Records a metronome click, if clicks are being recorded. Clicks of a
+    /// running metronome during the count-in are left out.
+    pub fn record_click(&mut self, channel: u4, message: MidiMessage) {
+        if !self.clock.has_started() {
+            return;
+        }
+        let tick = self.clock.tick();
+        if let Some(clicks) = &mut self.clicks {
+            // Releases a click from before the start, which was left out.
+            if clicks.is_empty() && matches!(message, MidiMessage::NoteOff { .. }) {
+                return;
+            }
+            clicks.push((tick, TrackEventKind::Midi { channel, message }));
+        }
+    }
+
+    /// Builds the file recorded so far. The recorder is left as it is, so the
+    /// take is not lost if saving the file fails.
+    pub fn finish(&self) -> Smf<'_> {
+        let end_tick = self.clock.tick();
+
+        let mut smf = Smf::new(self.header);
+        smf.tracks = self.tracks.clone();
+        // Empty for an overdub whose inputs are recorded separately.
+        if !self.events.is_empty() {
+            smf.tracks.push(to_track(self.events.clone(), end_tick));
+        }
+
+        for input in self.inputs.iter().flatten() {
+            let name = input.name.as_bytes();
+            let mut events = vec![(0, TrackEventKind::Meta(MetaMessage::TrackName(name)))];
+            events.extend(input.events.iter().cloned());
+            smf.tracks.push(to_track(events, end_tick));
+            smf.header.format = Format::Parallel;
+        }
+
+        if let Some(clicks) = &self.clicks {
+            let mut events = vec![(
+                0,
+                TrackEventKind::Meta(MetaMessage::TrackName(CLICK_TRACK_NAME)),
+            )];
+            events.extend(clicks.iter().cloned());
+            smf.tracks.push(to_track(events, end_tick));
+            smf.header.format = Format::Parallel;
+        }
+
+        smf
+    }
+}
+
+/// Whether the track holds metronome clicks recorded with a take.
+pub fn is_click_track(track: &Track) -> bool {
+    track
+        .iter()
+        .any(|event| event.kind == TrackEventKind::Meta(MetaMessage::TrackName(CLICK_TRACK_NAME)))
+}
+
+/// Undoes `transpose` on a note message, or returns `None` if the note would
+/// fall outside the MIDI range. Other messages are returned unchanged.
+fn untranspose(channel: u4, message: MidiMessage, transpose: i8) -> Option<MidiMessage> {
+    let untranspose_key = |key: u7| {
+        transpose_key(channel.as_int(), key.as_int(), transpose.saturating_neg()).map(u7::new)
+    };
+    let message = match message {
+        MidiMessage::NoteOn { key, vel } => MidiMessage::NoteOn {
+            key: untranspose_key(key)?,
+            vel,
+        },
+        MidiMessage::NoteOff { key, vel } => MidiMessage::NoteOff {
+            key: untranspose_key(key)?,
+            vel,
+        },
+        MidiMessage::Aftertouch { key, vel } => MidiMessage::Aftertouch {
+            key: untranspose_key(key)?,
+            vel,
+        },
+        message => message,
+    };
+    Some(message)
+}
+
+/// Builds a track from events at absolute ticks, ending at `end_tick` or
+/// its last event, whichever is later.
+fn to_track<'a>(mut events: Vec<(u64, TrackEventKind<'a>)>, end_tick: u64) -> Track<'a> {
+    events.sort_by_key(|(tick, _)| *tick);
+
+    let mut last_tick = 0;
+    let mut track = Vec::with_capacity(events.len() + 1);
+    for (tick, kind) in events {
+        track.push(TrackEvent {
+            delta: u28::from_int_lossy((tick - last_tick) as u32),
+            kind,
+        });
+        last_tick = tick;
+    }
+
+    track.push(TrackEvent {
+        delta: u28::from_int_lossy((end_tick.max(last_tick) - last_tick) as u32),
+        kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+    });
+    track
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+pub enum SaveRecordingError {
+    NotRecording,
+    /// The app data directory could not be resolved.
+    NoDataDirectory(String),
+    /// Creating the target directory or writing the file failed.
+    Io(String),
+}
+
+impl std::fmt::Display for SaveRecordingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SaveRecordingError::NotRecording => write!(f, "no recording in progress"),
+            SaveRecordingError::NoDataDirectory(err) => {
+                write!(f, "app data directory not found: {}", err)
+            }
+            SaveRecordingError::Io(err) => write!(f, "error while saving recording: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SaveRecordingError {}
+
+impl From<LibraryError> for SaveRecordingError {
+    fn from(err: LibraryError) -> Self {
+        match err {
+            LibraryError::NoDataDirectory(err) => SaveRecordingError::NoDataDirectory(err),
+            err => SaveRecordingError::Io(err.to_string()),
+        }
+    }
+}
+
+impl From<std::io::Error> for SaveRecordingError {
+    fn from(err: std::io::Error) -> Self {
+        SaveRecordingError::Io(err.to_string())
+    }
+}
+
+/// Path for a new recording in `dir`, named after the current UTC time,
+/// e.g. `recording-20240131-184502.mid`. A number is appended if a recording
+/// with that name already exists.
+pub fn default_recording_path(dir: &Path) -> PathBuf {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    let (year, month, day) = civil_date(secs / 86_400);
+    let time = secs % 86_400;
+    let stem = format!(
+        "recording-{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    );
+
+    let mut path = dir.join(format!("{}.mid", stem));
+    let mut count = 1;
+    while path.exists() {
+        count += 1;
+        path = dir.join(format!("{}-{}.mid", stem, count));
+    }
+    path
+}
+
+/// Converts days since 1970-01-01 into a (year, month, day) date.
+fn civil_date(days: u64) -> (u64, u64, u64) {
+    // Counts from 0000-03-01, so the leap day is the last day of a year.
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn input(index: &str) -> AvailableMidiInput {
+        AvailableMidiInput {
+            name: format!("{} keyboard", index),
+            index: index.to_string(),
+        }
+    }
+
+    fn note_on(key: u8) -> MidiMessage {
+        MidiMessage::NoteOn {
+            key: u7::new(key),
+            vel: u7::new(100),
+        }
+    }
+
+    fn note_off(key: u8) -> MidiMessage {
+        MidiMessage::NoteOff {
+            key: u7::new(key),
+            vel: u7::new(0),
+        }
+    }
+
+    /// Every event of a track with its absolute tick.
+    fn events<'a>(track: &Track<'a>) -> Vec<(u64, TrackEventKind<'a>)> {
+        let mut tick = 0;
+        track
+            .iter()
+            .map(|event| {
+                tick += event.delta.as_int() as u64;
+                (tick, event.kind)
+            })
+            .collect()
+    }
+
+    fn kinds<'a>(track: &Track<'a>) -> Vec<TrackEventKind<'a>> {
+        events(track).into_iter().map(|(_, kind)| kind).collect()
+    }
+
+    fn midi(channel: u8, message: MidiMessage) -> TrackEventKind<'static> {
+        TrackEventKind::Midi {
+            channel: u4::new(channel),
+            message,
+        }
+    }
+
+    #[test]
+    fn converts_microseconds_to_ticks_at_the_recording_tempo() {
+        let settings = RecordingSettings::default();
+
+        assert_eq!(settings.micros_per_quarter(), 500_000);
+        assert_eq!(settings.micros_to_ticks(500_000), 480);
+        assert_eq!(settings.micros_to_ticks(250_000), 240);
+        // Rounds to the nearest tick, which lasts about 1042 microseconds.
+        assert_eq!(settings.micros_to_ticks(520), 0);
+        assert_eq!(settings.micros_to_ticks(521), 1);
+
+        let slow = RecordingSettings {
+            bpm: 60.0,
+            ppq: 96,
+            ..settings
+        };
+        assert_eq!(slow.micros_to_ticks(1_500_000), 144);
+    }
+
+    #[test]
+    fn validates_settings() {
+        let settings = RecordingSettings::default();
+        assert!(settings.is_valid());
+        assert!(RecordingSettings {
+            time_signature: (6, 8),
+            ..settings
+        }
+        .is_valid());
+
+        for invalid in [
+            RecordingSettings {
+                bpm: f64::NAN,
+                ..settings
+            },
+            RecordingSettings {
+                bpm: 0.5,
+                ..settings
+            },
+            // A quarter note of 20 seconds does not fit the Tempo event.
+            RecordingSettings {
+                bpm: 3.0,
+                ..settings
+            },
+            RecordingSettings { ppq: 0, ..settings },
+            RecordingSettings {
+                time_signature: (0, 4),
+                ..settings
+            },
+            RecordingSettings {
+                time_signature: (3, 5),
+                ..settings
+            },
+        ] {
+            assert!(!invalid.is_valid(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn records_wall_clock_time_as_ticks() {
+        let start = Instant::now() - Duration::from_millis(500);
+        let mut recorder = Recorder::new(RecordingSettings::default(), start);
+        recorder.record(&input("a"), u4::new(0), note_on(60));
+        let smf = recorder.finish();
+
+        assert_eq!(smf.header.format, Format::SingleTrack);
+        assert_eq!(smf.tracks.len(), 1);
+        let events = events(&smf.tracks[0]);
+        assert_eq!(
+            events[..2],
+            [
+                (
+                    0,
+                    TrackEventKind::Meta(MetaMessage::Tempo(u24::new(500_000)))
+                ),
+                (
+                    0,
+                    TrackEventKind::Meta(MetaMessage::TimeSignature(4, 2, 24, 8))
+                ),
+            ]
+        );
+        // Half a second at 120 bpm is a quarter note, give or take the time
+        // the test takes.
+        let (tick, kind) = events[2];
+        assert!((480..600).contains(&tick), "{}", tick);
+        assert_eq!(kind, midi(0, note_on(60)));
+    }
+
+    #[test]
+    fn events_during_the_count_in_land_on_the_first_tick() {
+        let start = Instant::now() + Duration::from_secs(10);
+        let settings = RecordingSettings {
+            click_track: true,
+            ..RecordingSettings::default()
+        };
+        let mut recorder = Recorder::new(settings, start);
+        recorder.record(&input("a"), u4::new(0), note_on(60));
+        recorder.record_click(u4::new(9), note_on(76));
+        let smf = recorder.finish();
+
+        assert_eq!(events(&smf.tracks[0])[2], (0, midi(0, note_on(60))));
+        let clicks = events(&smf.tracks[1]);
+        assert!(is_click_track(&smf.tracks[1]));
+        assert_eq!(clicks.len(), 2, "{:?}", clicks);
+    }
+
+    #[test]
+    fn writes_clicks_to_a_track_of_their_own() {
+        let settings = RecordingSettings {
+            click_track: true,
+            ..RecordingSettings::default()
+        };
+        let mut recorder = Recorder::new(settings, Instant::now());
+        // The release of a click from before the start is left out.
+        recorder.record_click(u4::new(9), note_off(77));
+        recorder.record_click(u4::new(9), note_on(76));
+        recorder.record_click(u4::new(9), note_off(76));
+        let smf = recorder.finish();
+
+        assert_eq!(smf.header.format, Format::Parallel);
+        assert_eq!(smf.tracks.len(), 2);
+        assert!(!is_click_track(&smf.tracks[0]));
+        assert!(is_click_track(&smf.tracks[1]));
+        let clicks = kinds(&smf.tracks[1])
+            .into_iter()
+            .filter(|kind| matches!(kind, TrackEventKind::Midi { .. }))
+            .collect::<Vec<_>>();
+        assert_eq!(clicks, vec![midi(9, note_on(76)), midi(9, note_off(76))]);
+    }
+
+    #[test]
+    fn records_each_input_to_a_named_track() {
+        let settings = RecordingSettings {
+            separate_inputs: true,
+            ..RecordingSettings::default()
+        };
+        let mut recorder = Recorder::new(settings, Instant::now());
+        recorder.record(&input("b"), u4::new(0), note_on(60));
+        recorder.record(&input("a"), u4::new(1), note_on(64));
+        recorder.record(&input("b"), u4::new(0), note_off(60));
+        let smf = recorder.finish();
+
+        assert_eq!(smf.header.format, Format::Parallel);
+        assert_eq!(smf.tracks.len(), 3);
+        assert_eq!(
+            kinds(&smf.tracks[1])[..3],
+            [
+                TrackEventKind::Meta(MetaMessage::TrackName(b"b keyboard")),
+                midi(0, note_on(60)),
+                midi(0, note_off(60)),
+            ]
+        );
+        assert_eq!(
+            kinds(&smf.tracks[2])[..2],
+            [
+                TrackEventKind::Meta(MetaMessage::TrackName(b"a keyboard")),
+                midi(1, note_on(64)),
+            ]
+        );
+    }
+
+    #[test]
+    fn overdub_follows_the_playback_clock_after_the_file_tracks() {
+        let timing = Timing::Metrical(u15::new(480));
+        let mut smf = Smf::new(Header::new(Format::SingleTrack, timing));
+        smf.tracks.push(to_track(
+            vec![(0, TrackEventKind::Meta(MetaMessage::TrackName(b"Piano")))],
+            1920,
+        ));
+
+        // One second into the file at its default 120 bpm, transposed up.
+        let clock = PlaybackClock::paused(1_000_000, 2);
+        let mut recorder = Recorder::overdub(smf, clock, &RecordingSettings::default());
+        recorder.record(&input("a"), u4::new(0), note_on(62));
+        // Drums are not transposed.
+        recorder.record(&input("a"), u4::new(9), note_on(36));
+        let smf = recorder.finish();
+
+        assert_eq!(smf.header.format, Format::Parallel);
+        assert_eq!(smf.header.timing, timing);
+        assert_eq!(smf.tracks.len(), 2);
+        assert_eq!(
+            events(&smf.tracks[0])[0].1,
+            TrackEventKind::Meta(MetaMessage::TrackName(b"Piano"))
+        );
+        assert_eq!(
+            events(&smf.tracks[1]),
+            vec![
+                (0, TrackEventKind::Meta(MetaMessage::TrackName(b"Overdub"))),
+                (960, midi(0, note_on(60))),
+                (960, midi(9, note_on(36))),
+                (960, TrackEventKind::Meta(MetaMessage::EndOfTrack)),
+            ]
+        );
+    }
+
+    #[test]
+    fn untransposes_notes_only() {
+        assert_eq!(untranspose(u4::new(0), note_on(62), 2), Some(note_on(60)));
+        assert_eq!(untranspose(u4::new(0), note_on(1), 2), None);
+        assert_eq!(untranspose(u4::new(9), note_on(38), 2), Some(note_on(38)));
+
+        let sustain = MidiMessage::Controller {
+            controller: u7::new(64),
+            value: u7::new(127),
+        };
+        assert_eq!(untranspose(u4::new(0), sustain, 2), Some(sustain));
+    }
+
+    #[test]
+    fn converts_days_since_the_epoch_to_dates() {
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(civil_date(59), (1970, 3, 1));
+        assert_eq!(civil_date(789), (1972, 2, 29));
+        assert_eq!(civil_date(11_016), (2000, 2, 29));
+        assert_eq!(civil_date(11_017), (2000, 3, 1));
+        assert_eq!(civil_date(20_088), (2024, 12, 31));
+        // 2100 is not a leap year.
+        assert_eq!(civil_date(47_540), (2100, 2, 28));
+        assert_eq!(civil_date(47_541), (2100, 3, 1));
+    }
+}
@@ -0,0 +1,515 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    engine::{transpose_key, Jump},
+    playback::{Song, TrackSelection},
+};
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct ScoringSettings {
+    /// How far a played note may be from the expected one and still count as
+    /// a hit, in milliseconds.
+    pub tolerance_ms: u64,
+    /// Tracks whose notes are expected; every played track when `None`.
+    pub tracks: Option<Vec<usize>>,
+}
+
+impl Default for ScoringSettings {
+    fn default() -> Self {
+        ScoringSettings {
+            tolerance_ms: 150,
+            tracks: None,
+        }
+    }
+}
+
+/// A note, placed in song time in microseconds.
+#[derive(Debug, Clone, Copy)]
+pub struct ScoredNote {
+    pub time: u64,
+    pub key: u8,
+    pub velocity: u8,
+}
+
+impl ScoredNote {
+    /// Every NoteOn of the song on a track selected by `settings`.
+    pub fn from_song(
+        song: &Song,
+        tracks: &TrackSelection,
+        settings: &ScoringSettings,
+    ) -> Vec<ScoredNote> {
+        song.timeline
+            .iter()
+            .filter(|event| {
+                event.is_note_on
+                    && tracks.is_played(event.track)
+                    && settings
+                        .tracks
+                        .as_ref()
+                        .is_none_or(|scored| scored.contains(&event.track))
+            })
+            .map(|event| ScoredNote {
+                time: event.time,
+                key: event.message[1],
+                velocity: event.message[2],
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NoteOutcome {
+    Hit,
+    Missed,
+    Extra,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct NoteResult {
+    outcome: NoteOutcome,
+    /// Pass through the song the note belongs to, counting from 0. Seeking
+    /// and every repetition of a loop start a new pass.
+    pass: usize,
+    key: u8,
+    /// Time of the expected note; `None` for extra notes.
+    expected_ms: Option<u64>,
+    /// Time of the played note; `None` for missed notes.
+    played_ms: Option<u64>,
+    /// Played time minus expected time, positive when late.
+    timing_offset_ms: Option<i64>,
+    /// Played velocity minus expected velocity.
+    velocity_deviation: Option<i16>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ScoreSummary {
+    expected: usize,
+    hit: usize,
+    missed: usize,
+    extra: usize,
+    /// Hits divided by expected plus extra notes, from 0 to 1.
+    accuracy: f64,
+    mean_timing_offset_ms: f64,
+    mean_absolute_timing_offset_ms: f64,
+    mean_velocity_deviation: f64,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ScoreReport {
+    notes: Vec<NoteResult>,
+    summary: ScoreSummary,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+pub enum ScoreError {
+    /// A file could not be read.
+    Io(String),
+    /// A file is not a valid MIDI file.
+    InvalidFile(String),
+}
+
+impl std::fmt::Display for ScoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScoreError::Io(err) => write!(f, "error while reading file: {}", err),
+            ScoreError::InvalidFile(err) => write!(f, "invalid MIDI file: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ScoreError {}
+
+impl From<std::io::Error> for ScoreError {
+    fn from(err: std::io::Error) -> Self {
+        ScoreError::Io(err.to_string())
+    }
+}
+
+impl From<midly::Error> for ScoreError {
+    fn from(err: midly::Error) -> Self {
+        ScoreError::InvalidFile(err.to_string())
+    }
+}
+
+/// Aligns played notes with expected ones and scores the result.
+///
+/// Every pair of an expected and a played note on the same key within the
+/// tolerance is a candidate, and candidates are matched closest first, so a
+/// note played between two expected ones goes to the nearer of them.
+pub fn score(expected: &[ScoredNote], played: &[ScoredNote], tolerance_ms: u64) -> ScoreReport {
+    let tolerance = tolerance_ms * 1000;
+
+    let mut played_order = (0..played.len()).collect::<Vec<_>>();
+    played_order.sort_by_key(|index| played[*index].time);
+
+    let mut candidates = Vec::new();
+    for (expected_index, note) in expected.iter().enumerate() {
+        let first =
+            played_order.partition_point(|index| played[*index].time + tolerance < note.time);
+        for played_index in &played_order[first..] {
+            let played_note = &played[*played_index];
+            if played_note.time > note.time + tolerance {
+                break;
+            }
+            if played_note.key == note.key {
+                let distance = played_note.time.abs_diff(note.time);
+                candidates.push((distance, expected_index, *played_index));
+            }
+        }
+    }
+    candidates.sort();
+
+    let mut expected_match = vec![None; expected.len()];
+    let mut played_matched = vec![false; played.len()];
+    for (_, expected_index, played_index) in candidates {
+        if expected_match[expected_index].is_none() && !played_matched[played_index] {
+            expected_match[expected_index] = Some(played_index);
+            played_matched[played_index] = true;
+        }
+    }
+
+    let mut notes = Vec::with_capacity(expected.len() + played.len());
+    for (note, matched) in expected.iter().zip(&expected_match) {
+        notes.push(match matched {
+            Some(played_index) => {
+                let played_note = &played[*played_index];
+                NoteResult {
+                    outcome: NoteOutcome::Hit,
+                    pass: 0,
+                    key: note.key,
+                    expected_ms: Some(note.time / 1000),
+                    played_ms: Some(played_note.time / 1000),
+                    timing_offset_ms: Some((played_note.time as i64 - note.time as i64) / 1000),
+                    velocity_deviation: Some(played_note.velocity as i16 - note.velocity as i16),
+                }
+            }
+            None => NoteResult {
+                outcome: NoteOutcome::Missed,
+                pass: 0,
+                key: note.key,
+                expected_ms: Some(note.time / 1000),
+                played_ms: None,
+                timing_offset_ms: None,
+                velocity_deviation: None,
+            },
+        });
+    }
+    for (played_note, _) in played
+        .iter()
+        .zip(&played_matched)
+        .filter(|(_, matched)| !**matched)
+    {
+        notes.push(NoteResult {
+            outcome: NoteOutcome::Extra,
+            pass: 0,
+            key: played_note.key,
+            expected_ms: None,
+            played_ms: Some(played_note.time / 1000),
+            timing_offset_ms: None,
+            velocity_deviation: None,
+        });
+    }
+    notes.sort_by_key(|note| note.expected_ms.or(note.played_ms));
+
+    ScoreReport {
+        summary: summarize(&notes),
+        notes,
+    }
+}
+
+/// Shifts played notes by `offset_ms`, or without an offset so the first
+/// played note falls on the first expected one.
+pub fn align(expected: &[ScoredNote], played: &mut [ScoredNote], offset_ms: Option<i64>) {
+    let offset = match offset_ms {
+        Some(offset_ms) => offset_ms * 1000,
+        None => {
+            let first_expected = expected.iter().map(|note| note.time).min();
+            let first_played = played.iter().map(|note| note.time).min();
+            let (Some(first_expected), Some(first_played)) = (first_expected, first_played) else {
+                return;
+            };
+            first_expected as i64 - first_played as i64
+        }
+    };
+
+    for note in played {
+        note.time = note.time.saturating_add_signed(offset);
+    }
+}
+
+fn summarize(notes: &[NoteResult]) -> ScoreSummary {
+    let count = |outcome| notes.iter().filter(|note| note.outcome == outcome).count();
+    let hit = count(NoteOutcome::Hit);
+    let missed = count(NoteOutcome::Missed);
+    let extra = count(NoteOutcome::Extra);
+
+    let mean = |values: Vec<f64>| {
+        if values.is_empty() {
+            0.0
+        } else {
+            values.iter().sum::<f64>() / values.len() as f64
+        }
+    };
+    let offsets = notes
+        .iter()
+        .filter_map(|note| note.timing_offset_ms)
+        .map(|offset| offset as f64)
+        .collect::<Vec<_>>();
+    let velocity_deviations = notes
+        .iter()
+        .filter_map(|note| note.velocity_deviation)
+        .map(|deviation| deviation as f64)
+        .collect::<Vec<_>>();
+
+    ScoreSummary {
+        expected: hit + missed,
+        hit,
+        missed,
+        extra,
+        accuracy: if hit + missed + extra == 0 {
+            0.0
+        } else {
+            hit as f64 / (hit + missed + extra) as f64
+        },
+        mean_timing_offset_ms: mean(offsets.clone()),
+        mean_absolute_timing_offset_ms: mean(offsets.iter().map(|offset| offset.abs()).collect()),
+        mean_velocity_deviation: mean(velocity_deviations),
+    }
+}
+
+/// Collects notes played on the live input while the playback engine runs,
+/// to be scored against the song once the session ends.
+pub struct ScoringSession {
+    settings: ScoringSettings,
+    /// Pass of playback when scoring started, as counted by the engine.
+    first_pass: usize,
+    /// Song position when scoring started, in microseconds.
+    start: u64,
+    /// Played notes with the pass they were played in.
+    played: Vec<(usize, ScoredNote)>,
+}
+
+impl ScoringSession {
+    pub fn new(settings: ScoringSettings, pass: usize, start: u64) -> Self {
+        ScoringSession {
+            settings,
+            first_pass: pass,
+            start,
+            played: vec![],
+        }
+    }
+
+    /// Records a note played at song position `time` during `pass`.
+    /// `transpose` is the transposition playback is using, so the note is
+    /// compared in the key of the file; drums are not transposed.
+    pub fn note_on(
+        &mut self,
+        pass: usize,
+        time: u64,
+        channel: u8,
+        key: u8,
+        velocity: u8,
+        transpose: i8,
+    ) {
+        if let Some(key) = transpose_key(channel, key, transpose.saturating_neg()) {
+            self.played.push((
+                pass,
+                ScoredNote {
+                    time,
+                    key,
+                    velocity,
+                },
+            ));
+        }
+    }
+
+    /// Scores the session against the song notes played through between
+    /// where scoring started and `end`. `jumps` are every jump of the
+    /// playback; each pass between them is scored on its own, so a section
+    /// that is looped or sought back to is expected again every time.
+    pub fn finish(
+        self,
+        song: &Song,
+        tracks: &TrackSelection,
+        jumps: &[Jump],
+        end: u64,
+    ) -> ScoreReport {
+        let song_notes = ScoredNote::from_song(song, tracks, &self.settings);
+
+        // Each pass ends where playback jumped away, and the last one at `end`.
+        let mut ranges = vec![];
+        let mut start = self.start;
+        for jump in jumps.iter().skip(self.first_pass) {
+            ranges.push(start..jump.from);
+            start = jump.to;
+        }
+        ranges.push(start..end + 1);
+
+        let mut notes = vec![];
+        for (pass, range) in ranges.into_iter().enumerate() {
+            let expected = song_notes
+                .iter()
+                .filter(|note| range.contains(&note.time))
+                .copied()
+                .collect::<Vec<_>>();
+            let played = self
+                .played
+                .iter()
+                .filter(|(played_pass, _)| *played_pass == self.first_pass + pass)
+                .map(|(_, note)| *note)
+                .collect::<Vec<_>>();
+
+            let report = score(&expected, &played, self.settings.tolerance_ms);
+            notes.extend(
+                report
+                    .notes
+                    .into_iter()
+                    .map(|note| NoteResult { pass, ..note }),
+            );
+        }
+
+        ScoreReport {
+            summary: summarize(&notes),
+            notes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::playback::PlaybackEvent;
+
+    use super::*;
+
+    fn note(time_ms: u64, key: u8) -> ScoredNote {
+        ScoredNote {
+            time: time_ms * 1000,
+            key,
+            velocity: 100,
+        }
+    }
+
+    fn outcomes(report: &ScoreReport) -> Vec<(NoteOutcome, usize, Option<u64>)> {
+        report
+            .notes
+            .iter()
+            .map(|note| (note.outcome, note.pass, note.expected_ms))
+            .collect()
+    }
+
+    /// A song of NoteOns on key 60 at the given times.
+    fn song(times_ms: &[u64]) -> Song {
+        Song {
+            timeline: times_ms
+                .iter()
+                .map(|time_ms| PlaybackEvent {
+                    time: time_ms * 1000,
+                    track: 0,
+                    message: [0x90, 60, 100],
+                    time_length: 100,
+                    is_note_on: true,
+                })
+                .collect(),
+            bars: vec![0],
+        }
+    }
+
+    #[test]
+    fn scores_hits_misses_and_extras() {
+        let expected = [note(0, 60), note(500, 62)];
+        let played = [note(30, 60), note(500, 64)];
+        let report = score(&expected, &played, 150);
+
+        assert_eq!(report.notes[0].timing_offset_ms, Some(30));
+        assert_eq!(report.summary.hit, 1);
+        assert_eq!(report.summary.missed, 1);
+        assert_eq!(report.summary.extra, 1);
+        assert_eq!(report.summary.accuracy, 1.0 / 3.0);
+    }
+
+    #[test]
+    fn matches_the_closest_expected_note() {
+        let expected = [note(0, 60), note(200, 60)];
+        let played = [note(120, 60)];
+        let report = score(&expected, &played, 150);
+
+        assert_eq!(
+            outcomes(&report),
+            vec![
+                (NoteOutcome::Missed, 0, Some(0)),
+                (NoteOutcome::Hit, 0, Some(200)),
+            ]
+        );
+    }
+
+    #[test]
+    fn scores_every_pass_of_a_loop() {
+        let mut session = ScoringSession::new(ScoringSettings::default(), 0, 0);
+        for time in [0, 1_000_000, 2_000_000] {
+            session.note_on(0, time, 0, 60, 100, 0);
+        }
+        // The loop started over, and only its first note was played again.
+        session.note_on(1, 10_000, 0, 60, 100, 0);
+
+        let jumps = [Jump {
+            from: 2_500_000,
+            to: 0,
+        }];
+        let report = session.finish(
+            &song(&[0, 1000, 2000]),
+            &TrackSelection::default(),
+            &jumps,
+            1_500_000,
+        );
+
+        assert_eq!(
+            outcomes(&report),
+            vec![
+                (NoteOutcome::Hit, 0, Some(0)),
+                (NoteOutcome::Hit, 0, Some(1000)),
+                (NoteOutcome::Hit, 0, Some(2000)),
+                (NoteOutcome::Hit, 1, Some(0)),
+                (NoteOutcome::Missed, 1, Some(1000)),
+            ]
+        );
+    }
+
+    #[test]
+    fn compares_transposed_notes_in_the_key_of_the_file() {
+        let mut session = ScoringSession::new(ScoringSettings::default(), 0, 0);
+        session.note_on(0, 0, 0, 62, 100, 2);
+        let report = session.finish(&song(&[0]), &TrackSelection::default(), &[], 0);
+
+        assert_eq!(report.summary.hit, 1);
+    }
+
+    #[test]
+    fn compares_drums_untransposed() {
+        let mut session = ScoringSession::new(ScoringSettings::default(), 0, 0);
+        session.note_on(0, 0, 9, 60, 100, 2);
+        let report = session.finish(&song(&[0]), &TrackSelection::default(), &[], 0);
+
+        assert_eq!(report.summary.hit, 1);
+    }
+
+    #[test]
+    fn aligns_the_first_played_note() {
+        let expected = [note(1000, 60), note(1500, 62)];
+        let mut played = [note(3000, 60), note(3500, 62)];
+        align(&expected, &mut played, None);
+
+        assert_eq!(score(&expected, &played, 150).summary.hit, 2);
+    }
+
+    #[test]
+    fn aligns_by_a_given_offset() {
+        let expected = [note(1000, 60)];
+        let mut played = [note(3000, 60)];
+        align(&expected, &mut played, Some(-1900));
+
+        assert_eq!(played[0].time, 1_100_000);
+    }
+}
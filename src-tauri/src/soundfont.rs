@@ -0,0 +1,923 @@
+use std::{
+    collections::HashMap, f64::consts::FRAC_PI_4, ops::RangeInclusive, path::Path, sync::Arc,
+};
+
+use serde::Serialize;
+
+const GENERATOR_COUNT: usize = 61;
+
+// Generator numbers from the SoundFont 2.01 specification.
+const START_ADDRS_OFFSET: usize = 0;
+const END_ADDRS_OFFSET: usize = 1;
+const STARTLOOP_ADDRS_OFFSET: usize = 2;
+const ENDLOOP_ADDRS_OFFSET: usize = 3;
+const START_ADDRS_COARSE_OFFSET: usize = 4;
+const INITIAL_FILTER_FC: usize = 8;
+const END_ADDRS_COARSE_OFFSET: usize = 12;
+const PAN: usize = 17;
+const DELAY_VOL_ENV: usize = 33;
+const ATTACK_VOL_ENV: usize = 34;
+const HOLD_VOL_ENV: usize = 35;
+const DECAY_VOL_ENV: usize = 36;
+const SUSTAIN_VOL_ENV: usize = 37;
+const RELEASE_VOL_ENV: usize = 38;
+const KEYNUM_TO_VOL_ENV_HOLD: usize = 39;
+const KEYNUM_TO_VOL_ENV_DECAY: usize = 40;
+const INSTRUMENT: usize = 41;
+const KEY_RANGE: usize = 43;
+const VEL_RANGE: usize = 44;
+const STARTLOOP_ADDRS_COARSE_OFFSET: usize = 45;
+const KEYNUM: usize = 46;
+const VELOCITY: usize = 47;
+const INITIAL_ATTENUATION: usize = 48;
+const ENDLOOP_ADDRS_COARSE_OFFSET: usize = 50;
+const COARSE_TUNE: usize = 51;
+const FINE_TUNE: usize = 52;
+const SAMPLE_ID: usize = 53;
+const SAMPLE_MODES: usize = 54;
+const SCALE_TUNING: usize = 56;
+const EXCLUSIVE_CLASS: usize = 57;
+const OVERRIDING_ROOT_KEY: usize = 58;
+
+/// Generators that only make sense on an instrument zone, and are ignored on
+/// preset zones.
+const INSTRUMENT_ONLY: [usize; 14] = [
+    START_ADDRS_OFFSET,
+    END_ADDRS_OFFSET,
+    STARTLOOP_ADDRS_OFFSET,
+    ENDLOOP_ADDRS_OFFSET,
+    START_ADDRS_COARSE_OFFSET,
+    END_ADDRS_COARSE_OFFSET,
+    STARTLOOP_ADDRS_COARSE_OFFSET,
+    ENDLOOP_ADDRS_COARSE_OFFSET,
+    KEYNUM,
+    VELOCITY,
+    SAMPLE_ID,
+    SAMPLE_MODES,
+    EXCLUSIVE_CLASS,
+    OVERRIDING_ROOT_KEY,
+];
+
+/// A key or velocity range covering everything, as stored in a generator:
+/// the low byte is the bottom and the high byte the top of the range.
+const FULL_RANGE: i16 = 127 << 8;
+
+/// A RIFF chunk, as its id and body.
+type Chunk<'a> = ([u8; 4], &'a [u8]);
+
+/// The generators of a zone, as (generator, amount) pairs.
+type Zone = Vec<(usize, i16)>;
+
+/// Level below which an envelope in its release counts as silent.
+const SILENCE: f64 = 1e-4;
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+pub enum SoundFontError {
+    Io(String),
+    NotLoaded,
+    /// The file is not a valid SoundFont 2 file.
+    Invalid(String),
+    /// No preset with the requested bank and program.
+    PresetNotFound {
+        bank: u16,
+        program: u16,
+    },
+}
+
+impl std::fmt::Display for SoundFontError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SoundFontError::Io(err) => write!(f, "error while reading soundfont: {}", err),
+            SoundFontError::NotLoaded => write!(f, "no soundfont loaded"),
+            SoundFontError::Invalid(err) => write!(f, "invalid soundfont: {}", err),
+            SoundFontError::PresetNotFound { bank, program } => {
+                write!(f, "no preset {} in bank {}", program, bank)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SoundFontError {}
+
+impl From<std::io::Error> for SoundFontError {
+    fn from(err: std::io::Error) -> Self {
+        SoundFontError::Io(err.to_string())
+    }
+}
+
+fn invalid(message: &str) -> SoundFontError {
+    SoundFontError::Invalid(message.to_string())
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// Name stored in a fixed-size, zero-padded field.
+fn name_at(data: &[u8], offset: usize) -> String {
+    let field = &data[offset..offset + 20];
+    let len = field.iter().position(|byte| *byte == 0).unwrap_or(20);
+    String::from_utf8_lossy(&field[..len]).trim().to_string()
+}
+
+/// Splits the body of a RIFF chunk into its sub-chunks.
+fn sub_chunks(data: &[u8]) -> Result<Vec<Chunk<'_>>, SoundFontError> {
+    let mut chunks = vec![];
+    let mut rest = data;
+    while rest.len() >= 8 {
+        let id = rest[0..4].try_into().unwrap();
+        let len = u32_at(rest, 4) as usize;
+        let body = rest
+            .get(8..8 + len)
+            .ok_or_else(|| invalid("chunk runs past the end of the file"))?;
+        chunks.push((id, body));
+        // Chunks are padded to an even length.
+        rest = rest.get(8 + len + len % 2..).unwrap_or_default();
+    }
+    Ok(chunks)
+}
+
+/// The fixed-size records of a chunk of the preset data.
+fn records<'a>(
+    pdta: &HashMap<[u8; 4], &'a [u8]>,
+    id: &[u8; 4],
+    size: usize,
+) -> Result<Vec<&'a [u8]>, SoundFontError> {
+    let chunk = pdta.get(id).ok_or_else(|| {
+        SoundFontError::Invalid(format!("missing {} chunk", String::from_utf8_lossy(id)))
+    })?;
+    Ok(chunk.chunks_exact(size).collect())
+}
+
+/// The generators of the zones in `bag_range`.
+fn zones(
+    bags: &[&[u8]],
+    gens: &[&[u8]],
+    bag_range: std::ops::Range<usize>,
+) -> Result<Vec<Zone>, SoundFontError> {
+    bag_range
+        .map(|bag| {
+            let (Some(start), Some(end)) = (bags.get(bag), bags.get(bag + 1)) else {
+                return Err(invalid("zone index out of range"));
+            };
+            let (start, end) = (u16_at(start, 0) as usize, u16_at(end, 0) as usize);
+            let zone_gens = gens
+                .get(start..end)
+                .ok_or_else(|| invalid("generator index out of range"))?;
+            Ok(zone_gens
+                .iter()
+                .map(|gen| (u16_at(gen, 0) as usize, u16_at(gen, 2) as i16))
+                .filter(|(oper, _)| *oper < GENERATOR_COUNT)
+                .collect())
+        })
+        .collect()
+}
+
+/// Splits off the global zone, which is the first zone if it does not end
+/// with `terminal`, the generator that links a zone to what it plays.
+fn split_global(mut zones: Vec<Zone>, terminal: usize) -> (Zone, Vec<Zone>) {
+    let is_global = zones
+        .first()
+        .is_some_and(|zone| zone.last().map(|(oper, _)| *oper) != Some(terminal));
+    let global = if is_global { zones.remove(0) } else { vec![] };
+    // Local zones without the terminal generator are meaningless.
+    zones.retain(|zone| zone.last().map(|(oper, _)| *oper) == Some(terminal));
+    (global, zones)
+}
+
+fn range(amount: i16) -> RangeInclusive<u8> {
+    let [low, high] = amount.to_le_bytes();
+    low..=high
+}
+
+fn intersect(a: &RangeInclusive<u8>, b: &RangeInclusive<u8>) -> RangeInclusive<u8> {
+    *a.start().max(b.start())..=*a.end().min(b.end())
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SampleHeader {
+    start: u32,
+    end: u32,
+    loop_start: u32,
+    loop_end: u32,
+    sample_rate: u32,
+    original_pitch: u8,
+    /// Correction of the pitch in cents.
+    pitch_correction: i8,
+}
+
+/// A sample with the generators that shape it, for a range of keys and
+/// velocities.
+#[derive(Debug, Clone)]
+struct Region {
+    keys: RangeInclusive<u8>,
+    velocities: RangeInclusive<u8>,
+    sample: SampleHeader,
+    gens: [i16; GENERATOR_COUNT],
+}
+
+#[derive(Debug)]
+struct Preset {
+    name: String,
+    bank: u16,
+    program: u16,
+    regions: Vec<Region>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct PresetInfo {
+    name: String,
+    bank: u16,
+    program: u16,
+}
+
+/// The presets of a SoundFont 2 file, each flattened into the regions of
+/// its instruments, and the sample data they play.
+pub struct SoundFont {
+    presets: Vec<Preset>,
+    samples: Vec<i16>,
+}
+
+impl SoundFont {
+    pub fn load(path: &Path) -> Result<Self, SoundFontError> {
+        SoundFont::parse(&std::fs::read(path)?)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, SoundFontError> {
+        if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"sfbk" {
+            return Err(invalid("not a soundfont file"));
+        }
+        let riff_len = (u32_at(data, 4) as usize + 8).clamp(12, data.len());
+
+        let mut smpl: &[u8] = &[];
+        let mut pdta = HashMap::new();
+        for (id, body) in sub_chunks(&data[12..riff_len])? {
+            if &id != b"LIST" || body.len() < 4 {
+                continue;
+            }
+            match &body[0..4] {
+                b"sdta" => {
+                    for (id, body) in sub_chunks(&body[4..])? {
+                        if &id == b"smpl" {
+                            smpl = body;
+                        }
+                    }
+                }
+                b"pdta" => pdta.extend(sub_chunks(&body[4..])?),
+                _ => {}
+            }
+        }
+
+        let samples = smpl
+            .chunks_exact(2)
+            .map(|sample| i16::from_le_bytes([sample[0], sample[1]]))
+            .collect::<Vec<_>>();
+
+        let preset_headers = records(&pdta, b"phdr", 38)?;
+        let preset_bags = records(&pdta, b"pbag", 4)?;
+        let preset_gens = records(&pdta, b"pgen", 4)?;
+        let instrument_headers = records(&pdta, b"inst", 22)?;
+        let instrument_bags = records(&pdta, b"ibag", 4)?;
+        let instrument_gens = records(&pdta, b"igen", 4)?;
+        let sample_headers = records(&pdta, b"shdr", 46)?
+            .into_iter()
+            .map(|header| SampleHeader {
+                start: u32_at(header, 20),
+                end: u32_at(header, 24),
+                loop_start: u32_at(header, 28),
+                loop_end: u32_at(header, 32),
+                sample_rate: u32_at(header, 36),
+                original_pitch: header[40],
+                pitch_correction: header[41] as i8,
+            })
+            .collect::<Vec<_>>();
+
+        // Regions of every instrument, without the terminal record.
+        let instruments = instrument_headers
+            .windows(2)
+            .map(|headers| {
+                let bags = u16_at(headers[0], 20) as usize..u16_at(headers[1], 20) as usize;
+                let zones = zones(&instrument_bags, &instrument_gens, bags)?;
+                let (global, zones) = split_global(zones, SAMPLE_ID);
+
+                let mut regions = vec![];
+                for zone in zones {
+                    let mut gens = instrument_defaults();
+                    for (oper, amount) in global.iter().chain(&zone) {
+                        gens[*oper] = *amount;
+                    }
+                    let Some(sample) = sample_headers.get(gens[SAMPLE_ID] as u16 as usize) else {
+                        continue;
+                    };
+                    regions.push(Region {
+                        keys: range(gens[KEY_RANGE]),
+                        velocities: range(gens[VEL_RANGE]),
+                        sample: *sample,
+                        gens,
+                    });
+                }
+                Ok(regions)
+            })
+            .collect::<Result<Vec<_>, SoundFontError>>()?;
+
+        let presets = preset_headers
+            .windows(2)
+            .map(|headers| {
+                let bags = u16_at(headers[0], 24) as usize..u16_at(headers[1], 24) as usize;
+                let zones = zones(&preset_bags, &preset_gens, bags)?;
+                let (global, zones) = split_global(zones, INSTRUMENT);
+
+                let mut regions = vec![];
+                for zone in zones {
+                    // Preset generators are offsets added to the instrument's.
+                    let mut offsets = [0; GENERATOR_COUNT];
+                    offsets[KEY_RANGE] = FULL_RANGE;
+                    offsets[VEL_RANGE] = FULL_RANGE;
+                    for (oper, amount) in global.iter().chain(&zone) {
+                        offsets[*oper] = *amount;
+                    }
+                    let Some(instrument) = instruments.get(offsets[INSTRUMENT] as u16 as usize)
+                    else {
+                        continue;
+                    };
+
+                    for region in instrument {
+                        let mut region = region.clone();
+                        region.keys = intersect(&region.keys, &range(offsets[KEY_RANGE]));
+                        region.velocities =
+                            intersect(&region.velocities, &range(offsets[VEL_RANGE]));
+                        if region.keys.is_empty() || region.velocities.is_empty() {
+                            continue;
+                        }
+                        for (oper, offset) in offsets.iter().enumerate() {
+                            let is_additive = !INSTRUMENT_ONLY.contains(&oper)
+                                && !matches!(oper, INSTRUMENT | KEY_RANGE | VEL_RANGE);
+                            if is_additive {
+                                region.gens[oper] = region.gens[oper].saturating_add(*offset);
+                            }
+                        }
+                        regions.push(region);
+                    }
+                }
+
+                Ok(Preset {
+                    name: name_at(headers[0], 0),
+                    program: u16_at(headers[0], 20),
+                    bank: u16_at(headers[0], 22),
+                    regions,
+                })
+            })
+            .collect::<Result<Vec<_>, SoundFontError>>()?;
+
+        if presets.is_empty() {
+            return Err(invalid("no presets"));
+        }
+        Ok(SoundFont { presets, samples })
+    }
+
+    pub fn presets(&self) -> Vec<PresetInfo> {
+        (0..self.presets.len())
+            .map(|index| self.preset_info(index))
+            .collect()
+    }
+
+    pub fn preset_info(&self, index: usize) -> PresetInfo {
+        let preset = &self.presets[index];
+        PresetInfo {
+            name: preset.name.clone(),
+            bank: preset.bank,
+            program: preset.program,
+        }
+    }
+
+    /// Index of the preset with the given bank and program.
+    pub fn find_preset(&self, bank: u16, program: u16) -> Option<usize> {
+        self.presets
+            .iter()
+            .position(|preset| preset.bank == bank && preset.program == program)
+    }
+
+    /// The preset a synth starts with: the first one of the lowest bank and
+    /// program, usually a grand piano.
+    pub fn default_preset(&self) -> usize {
+        (0..self.presets.len())
+            .min_by_key(|index| (self.presets[*index].bank, self.presets[*index].program))
+            .unwrap()
+    }
+}
+
+fn instrument_defaults() -> [i16; GENERATOR_COUNT] {
+    let mut gens = [0; GENERATOR_COUNT];
+    gens[INITIAL_FILTER_FC] = 13500;
+    // Delays of the LFOs and stages of the envelopes but their sustain,
+    // about a millisecond each.
+    for oper in [21, 23, 25, 26, 27, 28, 30, 33, 34, 35, 36, 38] {
+        gens[oper] = -12000;
+    }
+    gens[KEY_RANGE] = FULL_RANGE;
+    gens[VEL_RANGE] = FULL_RANGE;
+    gens[KEYNUM] = -1;
+    gens[VELOCITY] = -1;
+    gens[SCALE_TUNING] = 100;
+    gens[OVERRIDING_ROOT_KEY] = -1;
+    gens
+}
+
+fn timecents_to_samples(timecents: i32, sample_rate: f64) -> f64 {
+    2f64.powf(timecents.clamp(-12000, 8000) as f64 / 1200.0) * sample_rate
+}
+
+/// Per-sample factor that lowers a level by 100 dB over `samples` samples,
+/// the rate at which SoundFont envelopes decay and release.
+fn decay_factor(samples: f64) -> f64 {
+    10f64.powf(-5.0 / samples.max(1.0))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Delay,
+    Attack,
+    Hold,
+    /// Decays towards the sustain level, and stays there.
+    Decay,
+    Release,
+    Finished,
+}
+
+/// The volume envelope of a SoundFont voice.
+struct Envelope {
+    stage: Stage,
+    level: f64,
+    /// Samples left in the delay, attack or hold stage.
+    remaining: u64,
+    attack: u64,
+    hold: u64,
+    decay: f64,
+    sustain: f64,
+    release: f64,
+}
+
+impl Envelope {
+    fn new(gens: &[i16; GENERATOR_COUNT], key: u8, sample_rate: f64) -> Self {
+        let gen = |oper: usize| gens[oper] as i32;
+        // Higher keys hold and decay for less time with positive values.
+        let key_offset = 60 - key as i32;
+        let hold = gen(HOLD_VOL_ENV) + gen(KEYNUM_TO_VOL_ENV_HOLD) * key_offset;
+        let decay = gen(DECAY_VOL_ENV) + gen(KEYNUM_TO_VOL_ENV_DECAY) * key_offset;
+        // Sustain is given as an attenuation in centibels.
+        let sustain = 10f64.powf(-(gen(SUSTAIN_VOL_ENV).clamp(0, 1440) as f64) / 200.0);
+
+        Envelope {
+            stage: Stage::Delay,
+            level: 0.0,
+            remaining: timecents_to_samples(gen(DELAY_VOL_ENV), sample_rate) as u64,
+            attack: timecents_to_samples(gen(ATTACK_VOL_ENV), sample_rate) as u64,
+            hold: timecents_to_samples(hold, sample_rate) as u64,
+            decay: decay_factor(timecents_to_samples(decay, sample_rate)),
+            sustain,
+            release: decay_factor(timecents_to_samples(gen(RELEASE_VOL_ENV), sample_rate)),
+        }
+    }
+
+    fn next(&mut self) -> f64 {
+        while self.remaining == 0
+            && matches!(self.stage, Stage::Delay | Stage::Attack | Stage::Hold)
+        {
+            (self.stage, self.remaining) = match self.stage {
+                Stage::Delay => (Stage::Attack, self.attack),
+                Stage::Attack => {
+                    self.level = 1.0;
+                    (Stage::Hold, self.hold)
+                }
+                _ => (Stage::Decay, 0),
+            };
+        }
+
+        match self.stage {
+            Stage::Delay | Stage::Hold => self.remaining -= 1,
+            Stage::Attack => {
+                self.level += (1.0 - self.level) / self.remaining as f64;
+                self.remaining -= 1;
+            }
+            Stage::Decay => self.level = (self.level * self.decay).max(self.sustain),
+            Stage::Release => {
+                self.level *= self.release;
+                if self.level < SILENCE {
+                    self.stage = Stage::Finished;
+                    self.level = 0.0;
+                }
+            }
+            Stage::Finished => {}
+        }
+        self.level
+    }
+
+    fn release(&mut self) {
+        if self.stage != Stage::Finished {
+            self.stage = Stage::Release;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LoopMode {
+    None,
+    Continuous,
+    /// Loops while the key is held, then plays on to the end of the sample.
+    UntilRelease,
+}
+
+/// Plays one region of a preset for one note.
+pub struct SampleVoice {
+    font: Arc<SoundFont>,
+    /// Position in the sample data, between samples.
+    position: f64,
+    /// Samples to advance by per output sample.
+    step: f64,
+    end: usize,
+    loop_start: usize,
+    loop_end: usize,
+    loop_mode: LoopMode,
+    gain: f64,
+    /// Gains of the left and right channel.
+    pan: (f64, f64),
+    envelope: Envelope,
+    released: bool,
+    finished: bool,
+}
+
+impl SampleVoice {
+    /// Voices for every region of `preset` that plays `key` at `velocity`.
+    pub fn for_note(
+        font: &Arc<SoundFont>,
+        preset: usize,
+        key: u8,
+        velocity: u8,
+        sample_rate: u32,
+    ) -> Vec<Self> {
+        font.presets[preset]
+            .regions
+            .iter()
+            .filter(|region| region.keys.contains(&key) && region.velocities.contains(&velocity))
+            .map(|region| SampleVoice::new(font.clone(), region, key, velocity, sample_rate))
+            .collect()
+    }
+
+    fn new(font: Arc<SoundFont>, region: &Region, key: u8, velocity: u8, sample_rate: u32) -> Self {
+        let gen = |oper: usize| region.gens[oper] as i64;
+        let sample = &region.sample;
+        let len = font.samples.len();
+        let address = |base: u32, fine: usize, coarse: usize| {
+            (base as i64 + gen(fine) + gen(coarse) * 32768).clamp(0, len as i64) as usize
+        };
+        let start = address(sample.start, START_ADDRS_OFFSET, START_ADDRS_COARSE_OFFSET);
+        let end = address(sample.end, END_ADDRS_OFFSET, END_ADDRS_COARSE_OFFSET);
+        let loop_start = address(
+            sample.loop_start,
+            STARTLOOP_ADDRS_OFFSET,
+            STARTLOOP_ADDRS_COARSE_OFFSET,
+        );
+        let loop_end = address(
+            sample.loop_end,
+            ENDLOOP_ADDRS_OFFSET,
+            ENDLOOP_ADDRS_COARSE_OFFSET,
+        );
+
+        let loop_mode = match gen(SAMPLE_MODES) & 3 {
+            _ if loop_start >= loop_end || loop_end > end => LoopMode::None,
+            1 => LoopMode::Continuous,
+            3 => LoopMode::UntilRelease,
+            _ => LoopMode::None,
+        };
+
+        let root_key = match gen(OVERRIDING_ROOT_KEY) {
+            root_key @ 0..=127 => root_key,
+            _ => sample.original_pitch.min(127) as i64,
+        };
+        // Ten octaves either way, as far as coarse tuning alone can go.
+        let cents = ((key as i64 - root_key) * gen(SCALE_TUNING)
+            + gen(COARSE_TUNE) * 100
+            + gen(FINE_TUNE)
+            + sample.pitch_correction as i64)
+            .clamp(-12000, 12000);
+        let step = 2f64.powf(cents as f64 / 1200.0) * sample.sample_rate.max(1) as f64
+            / sample_rate as f64;
+
+        // Initial attenuation is in centibels. Velocity follows roughly the
+        // curve of the default velocity-to-attenuation modulator.
+        let attenuation = 10f64.powf(-(gen(INITIAL_ATTENUATION).clamp(0, 1440) as f64) / 200.0);
+        let velocity = velocity as f64 / 127.0;
+        let position = (gen(PAN).clamp(-500, 500) as f64 / 500.0 + 1.0) * FRAC_PI_4;
+
+        SampleVoice {
+            envelope: Envelope::new(&region.gens, key, sample_rate as f64),
+            font,
+            position: start as f64,
+            step,
+            end,
+            loop_start,
+            loop_end,
+            loop_mode,
+            gain: attenuation * velocity * velocity,
+            pan: (position.cos(), position.sin()),
+            released: false,
+            finished: start >= end,
+        }
+    }
+
+    fn is_looping(&self) -> bool {
+        match self.loop_mode {
+            LoopMode::None => false,
+            LoopMode::Continuous => true,
+            LoopMode::UntilRelease => !self.released,
+        }
+    }
+
+    /// The next frame, as a left and right sample.
+    pub fn next(&mut self) -> (f64, f64) {
+        if self.finished {
+            return (0.0, 0.0);
+        }
+
+        let index = self.position as usize;
+        let fraction = self.position - index as f64;
+        let next_index = if self.is_looping() && index + 1 >= self.loop_end {
+            self.loop_start
+        } else {
+            index + 1
+        };
+        let sample_at = |index: usize| {
+            self.font.samples.get(index).copied().unwrap_or_default() as f64 / 32768.0
+        };
+        let (current, next) = (sample_at(index), sample_at(next_index));
+        let sample = (current + (next - current) * fraction) * self.gain * self.envelope.next();
+
+        self.position += self.step;
+        if self.is_looping() {
+            if self.position >= self.loop_end as f64 {
+                let loop_start = self.loop_start as f64;
+                let loop_len = (self.loop_end - self.loop_start) as f64;
+                self.position = loop_start + (self.position - loop_start) % loop_len;
+            }
+        } else if self.position >= self.end as f64 {
+            self.finished = true;
+        }
+        self.finished |= self.envelope.stage == Stage::Finished;
+
+        (sample * self.pan.0, sample * self.pan.1)
+    }
+
+    pub fn release(&mut self) {
+        self.released = true;
+        self.envelope.release();
+    }
+
+    pub fn level(&self) -> f64 {
+        if self.finished {
+            return 0.0;
+        }
+        self.gain * self.envelope.level
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend((body.len() as u32).to_le_bytes());
+        chunk.extend(body);
+        if body.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    fn list(kind: &[u8; 4], chunks: &[Vec<u8>]) -> Vec<u8> {
+        chunk(b"LIST", &[kind.to_vec(), chunks.concat()].concat())
+    }
+
+    fn name(name: &str) -> Vec<u8> {
+        let mut field = name.as_bytes().to_vec();
+        field.resize(20, 0);
+        field
+    }
+
+    fn bags(gen_indices: &[u16]) -> Vec<u8> {
+        gen_indices
+            .iter()
+            .flat_map(|index| [index.to_le_bytes(), [0, 0]].concat())
+            .collect()
+    }
+
+    /// Generators of a zone, followed by the terminal record of the list.
+    fn gens(gens: &[(usize, i16)]) -> Vec<u8> {
+        gens.iter()
+            .chain(&[(0, 0)])
+            .flat_map(|(oper, amount)| {
+                [(*oper as u16).to_le_bytes(), amount.to_le_bytes()].concat()
+            })
+            .collect()
+    }
+
+    fn preset_header(preset_name: &str, program: u16, bag: u16) -> Vec<u8> {
+        [
+            name(preset_name),
+            program.to_le_bytes().to_vec(),
+            0u16.to_le_bytes().to_vec(),
+            bag.to_le_bytes().to_vec(),
+            vec![0; 12],
+        ]
+        .concat()
+    }
+
+    fn sample_header(sample_name: &str, addresses: [u32; 5], original_pitch: u8) -> Vec<u8> {
+        [
+            name(sample_name),
+            addresses
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect(),
+            vec![original_pitch, 0, 0, 0, 1, 0],
+        ]
+        .concat()
+    }
+
+    /// A SoundFont with one preset, tuned up an octave, of an instrument
+    /// tuned down an octave, playing 16 samples looped from 4 to 12 on keys
+    /// 60 to 72. The preset data chunk with id `pdta_without` is left out.
+    fn test_font(pdta_without: &[u8; 4]) -> Vec<u8> {
+        let samples = (0..16i16)
+            .flat_map(|sample| (sample * 1000).to_le_bytes())
+            .collect::<Vec<_>>();
+        let pdta = [
+            chunk(
+                b"phdr",
+                &[preset_header("Piano", 0, 0), preset_header("EOP", 0, 1)].concat(),
+            ),
+            chunk(b"pbag", &bags(&[0, 2])),
+            chunk(b"pgen", &gens(&[(COARSE_TUNE, 12), (INSTRUMENT, 0)])),
+            chunk(
+                b"inst",
+                &[
+                    name("Piano"),
+                    0u16.to_le_bytes().to_vec(),
+                    name("EOI"),
+                    1u16.to_le_bytes().to_vec(),
+                ]
+                .concat(),
+            ),
+            chunk(b"ibag", &bags(&[0, 4])),
+            chunk(
+                b"igen",
+                &gens(&[
+                    (KEY_RANGE, 60 | 72 << 8),
+                    (COARSE_TUNE, -12),
+                    (SAMPLE_MODES, 1),
+                    (SAMPLE_ID, 0),
+                ]),
+            ),
+            chunk(
+                b"shdr",
+                &[
+                    sample_header("Ramp", [0, 16, 4, 12, 44_100], 60),
+                    sample_header("EOS", [0; 5], 0),
+                ]
+                .concat(),
+            ),
+        ]
+        .into_iter()
+        .filter(|chunk| &chunk[0..4] != pdta_without)
+        .collect::<Vec<_>>();
+
+        chunk(
+            b"RIFF",
+            &[
+                b"sfbk".to_vec(),
+                list(b"INFO", &[chunk(b"INAM", b"Test\0")]),
+                list(b"sdta", &[chunk(b"smpl", &samples)]),
+                list(b"pdta", &pdta),
+            ]
+            .concat(),
+        )
+    }
+
+    fn parse_test_font() -> Arc<SoundFont> {
+        Arc::new(SoundFont::parse(&test_font(b"none")).unwrap())
+    }
+
+    /// A voice of the test font's only region, with its generators changed.
+    fn voice(key: u8, sample_rate: u32, gens: &[(usize, i16)]) -> SampleVoice {
+        let font = parse_test_font();
+        let mut region = font.presets[0].regions[0].clone();
+        for (oper, amount) in gens {
+            region.gens[*oper] = *amount;
+        }
+        SampleVoice::new(font, &region, key, 127, sample_rate)
+    }
+
+    #[test]
+    fn parses_presets_and_their_regions() {
+        let font = parse_test_font();
+
+        assert_eq!(font.presets.len(), 1);
+        assert_eq!(font.preset_info(0).name, "Piano");
+        assert_eq!(font.find_preset(0, 0), Some(0));
+        assert_eq!(font.find_preset(0, 1), None);
+        assert_eq!(font.samples.len(), 16);
+        assert_eq!(font.samples[3], 3000);
+
+        let region = &font.presets[0].regions[0];
+        assert_eq!(region.keys, 60..=72);
+        assert_eq!(region.velocities, 0..=127);
+        assert_eq!(region.sample.loop_start, 4);
+        // Preset generators are added to the instrument's.
+        assert_eq!(region.gens[COARSE_TUNE], 0);
+    }
+
+    #[test]
+    fn plays_only_regions_covering_the_note() {
+        let font = parse_test_font();
+
+        assert_eq!(SampleVoice::for_note(&font, 0, 60, 100, 44_100).len(), 1);
+        assert_eq!(SampleVoice::for_note(&font, 0, 72, 100, 44_100).len(), 1);
+        assert!(SampleVoice::for_note(&font, 0, 59, 100, 44_100).is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        let data = test_font(b"none");
+
+        assert!(matches!(
+            SoundFont::parse(b"RIFF\x04\0\0\0WAVE"),
+            Err(SoundFontError::Invalid(_))
+        ));
+        assert!(matches!(
+            SoundFont::parse(&data[..data.len() - 10]),
+            Err(SoundFontError::Invalid(_))
+        ));
+        assert!(matches!(
+            SoundFont::parse(&test_font(b"shdr")),
+            Err(SoundFontError::Invalid(message)) if message == "missing shdr chunk"
+        ));
+    }
+
+    #[test]
+    fn steps_through_the_sample_at_the_pitch_of_the_key() {
+        assert_eq!(voice(60, 44_100, &[]).step, 1.0);
+        assert_eq!(voice(72, 44_100, &[]).step, 2.0);
+        assert_eq!(voice(60, 22_050, &[]).step, 2.0);
+    }
+
+    #[test]
+    fn loops_within_the_loop_points() {
+        let mut voice = voice(72, 44_100, &[]);
+
+        // Plays into the loop from the start of the sample, stepping by two.
+        let positions = (0..6)
+            .map(|_| {
+                voice.next();
+                voice.position
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(positions, [2.0, 4.0, 6.0, 8.0, 10.0, 4.0]);
+
+        for _ in 0..100 {
+            voice.next();
+            assert!((4.0..12.0).contains(&voice.position));
+            assert!(!voice.is_finished());
+        }
+    }
+
+    #[test]
+    fn finishes_at_the_end_of_an_unlooped_sample() {
+        let mut voice = voice(72, 44_100, &[(SAMPLE_MODES, 0)]);
+
+        for _ in 0..7 {
+            voice.next();
+        }
+        assert!(!voice.is_finished());
+        voice.next();
+        assert!(voice.is_finished());
+        assert_eq!(voice.next(), (0.0, 0.0));
+    }
+
+    #[test]
+    fn clamps_extreme_tuning() {
+        let mut voice = voice(127, 1, &[(COARSE_TUNE, i16::MAX)]);
+
+        assert_eq!(voice.step, 1024.0 * 44_100.0);
+        voice.next();
+        assert!((4.0..12.0).contains(&voice.position));
+    }
+}
@@ -0,0 +1,587 @@
+use std::{
+    f64::consts::{FRAC_PI_4, TAU},
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use midly::Smf;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    playback::Song,
+    soundfont::{SampleVoice, SoundFont},
+};
+
+/// Voices sounding at once; the quietest is cut when another note starts.
+const MAX_VOICES: usize = 64;
+const PARTIALS: u32 = 12;
+/// Stretches the partials like the stiffness of a real string does.
+const INHARMONICITY: f64 = 0.0004;
+const ATTACK_SECS: f64 = 0.002;
+/// Time constant of the damper once a note is released.
+const RELEASE_SECS: f64 = 0.08;
+/// Level below which a voice is dropped.
+const SILENCE: f64 = 1e-4;
+/// Frames rendered at once when rendering a file.
+const RENDER_BLOCK: usize = 512;
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(default)]
+pub struct SynthSettings {
+    pub sample_rate: u32,
+    /// Master volume, from 0 to 1.
+    pub volume: f64,
+    /// Longest time rendered after the last event while notes ring out, in
+    /// milliseconds.
+    pub max_tail_ms: u32,
+}
+
+impl Default for SynthSettings {
+    fn default() -> Self {
+        SynthSettings {
+            sample_rate: 44_100,
+            volume: 0.5,
+            max_tail_ms: 5000,
+        }
+    }
+}
+
+impl SynthSettings {
+    pub fn is_valid(&self) -> bool {
+        (8000..=192_000).contains(&self.sample_rate) && (0.0..=1.0).contains(&self.volume)
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+pub enum RenderError {
+    InvalidSettings,
+    /// The MIDI file could not be read or parsed, or the WAV file written.
+    Io(String),
+}
+
+impl std::fmt::Display for RenderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RenderError::InvalidSettings => write!(f, "invalid synth settings"),
+            RenderError::Io(err) => write!(f, "error while rendering file: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+impl From<std::io::Error> for RenderError {
+    fn from(err: std::io::Error) -> Self {
+        RenderError::Io(err.to_string())
+    }
+}
+
+/// A decaying sine, advanced by rotating it one step further every sample.
+/// The step includes the decay, so the length of (re, im) is its amplitude.
+struct Partial {
+    re: f64,
+    im: f64,
+    step_re: f64,
+    step_im: f64,
+}
+
+impl Partial {
+    fn level(&self) -> f64 {
+        self.re.hypot(self.im)
+    }
+}
+
+/// A struck string: a handful of partials that each fade on their own, the
+/// higher ones faster, until the damper stops them all.
+struct PianoVoice {
+    partials: Vec<Partial>,
+    /// Gains of the left and right channel.
+    pan: (f64, f64),
+    /// Frames played so far, for the attack.
+    age: u64,
+    attack: u64,
+    /// Whether the damper is on the string.
+    damped: bool,
+    damper: f64,
+    damper_step: f64,
+}
+
+impl PianoVoice {
+    fn new(key: u8, velocity: u8, sample_rate: u32) -> Self {
+        let sample_rate = sample_rate as f64;
+        let frequency = 440.0 * 2f64.powf((key as f64 - 69.0) / 12.0);
+        let velocity = velocity as f64 / 127.0;
+        // Harder strikes are louder and brighter.
+        let loudness = velocity.powf(1.7);
+        let rolloff = 2.2 - 1.2 * velocity;
+        // Seconds for the fundamental to fade by 60 dB, shorter up high.
+        let decay_secs = 20.0 * 2f64.powf(-(key as f64 - 21.0) / 24.0);
+
+        let amplitudes = (1..=PARTIALS)
+            .map(|n| n as f64)
+            .map(|n| (n, n.powf(-rolloff)))
+            .collect::<Vec<_>>();
+        let total = amplitudes
+            .iter()
+            .map(|(_, amplitude)| amplitude)
+            .sum::<f64>();
+
+        let partials = amplitudes
+            .into_iter()
+            .filter_map(|(n, amplitude)| {
+                let frequency = n * frequency * (1.0 + INHARMONICITY * n * n).sqrt();
+                if frequency >= sample_rate / 2.0 {
+                    return None;
+                }
+                let decay_secs = decay_secs / (1.0 + 0.3 * (n - 1.0));
+                let decay = 0.001f64.powf(1.0 / (decay_secs * sample_rate));
+                let angle = TAU * frequency / sample_rate;
+                Some(Partial {
+                    re: loudness * amplitude / total,
+                    im: 0.0,
+                    step_re: decay * angle.cos(),
+                    step_im: decay * angle.sin(),
+                })
+            })
+            .collect();
+
+        // Low notes lean left and high notes right, as seen from the bench.
+        let position = ((key as f64 - 64.0) / 64.0).clamp(-1.0, 1.0) * 0.4;
+        let angle = (position + 1.0) * FRAC_PI_4;
+
+        PianoVoice {
+            partials,
+            pan: (angle.cos(), angle.sin()),
+            age: 0,
+            attack: (ATTACK_SECS * sample_rate) as u64,
+            damped: false,
+            damper: 1.0,
+            damper_step: (-1.0 / (RELEASE_SECS * sample_rate)).exp(),
+        }
+    }
+
+    fn next(&mut self) -> (f64, f64) {
+        let mut sample = 0.0;
+        for partial in &mut self.partials {
+            sample += partial.im;
+            let re = partial.re * partial.step_re - partial.im * partial.step_im;
+            partial.im = partial.re * partial.step_im + partial.im * partial.step_re;
+            partial.re = re;
+        }
+
+        if self.age < self.attack {
+            sample *= self.age as f64 / self.attack as f64;
+        }
+        self.age += 1;
+        if self.damped {
+            self.damper *= self.damper_step;
+        }
+        let sample = sample * self.damper;
+        (sample * self.pan.0, sample * self.pan.1)
+    }
+
+    fn level(&self) -> f64 {
+        self.partials.iter().map(Partial::level).sum::<f64>() * self.damper
+    }
+}
+
+enum Sound {
+    Piano(PianoVoice),
+    Sample(SampleVoice),
+}
+
+struct Voice {
+    channel: u8,
+    key: u8,
+    /// Whether the key is still down.
+    held: bool,
+    /// Whether the note is fading: the key is up and the sustain pedal is not
+    /// holding it.
+    released: bool,
+    sound: Sound,
+}
+
+impl Voice {
+    fn next(&mut self) -> (f64, f64) {
+        match &mut self.sound {
+            Sound::Piano(piano) => piano.next(),
+            Sound::Sample(sample) => sample.next(),
+        }
+    }
+
+    fn release(&mut self) {
+        self.held = false;
+        if self.released {
+            return;
+        }
+        self.released = true;
+        match &mut self.sound {
+            Sound::Piano(piano) => piano.damped = true,
+            Sound::Sample(sample) => sample.release(),
+        }
+    }
+
+    fn level(&self) -> f64 {
+        match &self.sound {
+            Sound::Piano(piano) => piano.level(),
+            Sound::Sample(sample) => sample.level(),
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        match &self.sound {
+            Sound::Piano(piano) => piano.level() < SILENCE,
+            Sound::Sample(sample) => sample.is_finished(),
+        }
+    }
+}
+
+/// What the synth plays its notes with.
+#[derive(Clone, Default)]
+pub enum Instrument {
+    /// The built-in piano, made of sines.
+    #[default]
+    Piano,
+    /// A preset of a loaded SoundFont, by its index.
+    SoundFont { font: Arc<SoundFont>, preset: usize },
+}
+
+/// A deterministic software synth playing raw MIDI messages on all channels
+/// with one instrument, rendering interleaved stereo.
+pub struct Synth {
+    sample_rate: u32,
+    volume: f64,
+    instrument: Instrument,
+    voices: Vec<Voice>,
+    /// Whether the sustain pedal is down, per channel.
+    sustain: [bool; 16],
+}
+
+impl Synth {
+    pub fn new(settings: &SynthSettings, instrument: Instrument) -> Self {
+        Synth {
+            sample_rate: settings.sample_rate,
+            volume: settings.volume,
+            instrument,
+            voices: vec![],
+            sustain: [false; 16],
+        }
+    }
+
+    /// Changes the rate of the frames rendered, for an output that decides
+    /// it. Notes that are sounding keep their pitch only at the old rate.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+    }
+
+    /// Switches instruments. Notes that are sounding keep their instrument.
+    pub fn set_instrument(&mut self, instrument: Instrument) {
+        self.instrument = instrument;
+    }
+
+    /// Plays a message, as sent to a MIDI output. Messages other than notes,
+    /// the sustain pedal and the all-notes-off controllers are ignored.
+    pub fn handle(&mut self, message: &[u8]) {
+        let [status, data1, data2, ..] = *message else {
+            return;
+        };
+        let channel = status & 0x0F;
+
+        match (status & 0xF0, data1, data2) {
+            (0x90, key, velocity) if velocity > 0 => self.note_on(channel, key, velocity),
+            (0x80 | 0x90, key, _) => self.note_off(channel, key),
+            (0xB0, 64, value) => self.set_sustain(channel, value >= 64),
+            // All Sound Off
+            (0xB0, 120, _) => self.voices.retain(|voice| voice.channel != channel),
+            // All Notes Off
+            (0xB0, 123, _) => {
+                let sustained = self.sustain[channel as usize];
+                for voice in &mut self.voices {
+                    if voice.channel == channel {
+                        voice.held = false;
+                        if !sustained {
+                            voice.release();
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn note_on(&mut self, channel: u8, key: u8, velocity: u8) {
+        // Striking a key again damps the note it was still playing.
+        for voice in &mut self.voices {
+            if voice.channel == channel && voice.key == key {
+                voice.release();
+            }
+        }
+
+        let sounds = match &self.instrument {
+            Instrument::Piano => vec![Sound::Piano(PianoVoice::new(
+                key,
+                velocity,
+                self.sample_rate,
+            ))],
+            Instrument::SoundFont { font, preset } => {
+                SampleVoice::for_note(font, *preset, key, velocity, self.sample_rate)
+                    .into_iter()
+                    .map(Sound::Sample)
+                    .collect()
+            }
+        };
+
+        while !self.voices.is_empty() && self.voices.len() + sounds.len() > MAX_VOICES {
+            let quietest = (0..self.voices.len())
+                .min_by(|a, b| self.voices[*a].level().total_cmp(&self.voices[*b].level()))
+                .unwrap();
+            self.voices.remove(quietest);
+        }
+        self.voices.extend(sounds.into_iter().map(|sound| Voice {
+            channel,
+            key,
+            held: true,
+            released: false,
+            sound,
+        }));
+    }
+
+    fn note_off(&mut self, channel: u8, key: u8) {
+        let sustained = self.sustain[channel as usize];
+        for voice in &mut self.voices {
+            if voice.channel == channel && voice.key == key && voice.held {
+                voice.held = false;
+                if !sustained {
+                    voice.release();
+                }
+            }
+        }
+    }
+
+    fn set_sustain(&mut self, channel: u8, down: bool) {
+        self.sustain[channel as usize] = down;
+        if down {
+            return;
+        }
+        for voice in &mut self.voices {
+            if voice.channel == channel && !voice.held {
+                voice.release();
+            }
+        }
+    }
+
+    /// Fills `out` with interleaved stereo frames.
+    pub fn render(&mut self, out: &mut [f32]) {
+        for frame in out.chunks_exact_mut(2) {
+            let (mut left, mut right) = (0.0, 0.0);
+            for voice in &mut self.voices {
+                let (voice_left, voice_right) = voice.next();
+                left += voice_left;
+                right += voice_right;
+            }
+            // Soft clipping keeps loud chords from wrapping around.
+            frame[0] = (left * self.volume).tanh() as f32;
+            frame[1] = (right * self.volume).tanh() as f32;
+        }
+        self.voices.retain(|voice| !voice.is_finished());
+    }
+
+    pub fn is_silent(&self) -> bool {
+        self.voices.is_empty()
+    }
+}
+
+/// Renders every track of `smf` to interleaved stereo samples, passed to
+/// `output` a block at a time, letting the last notes ring out for at most
+/// `max_tail_ms`.
+pub fn render_smf(
+    smf: &Smf,
+    settings: &SynthSettings,
+    instrument: Instrument,
+    mut output: impl FnMut(&[f32]) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut synth = Synth::new(settings, instrument);
+    let mut block = vec![0.0; RENDER_BLOCK * 2];
+    let mut rendered = 0;
+    let frame_at =
+        |micros: u64| (micros as u128 * settings.sample_rate as u128 / 1_000_000) as usize;
+
+    for event in Song::from_smf(smf).timeline {
+        let frames = frame_at(event.time).saturating_sub(rendered);
+        render_frames(&mut synth, frames, &mut block, &mut output)?;
+        rendered += frames;
+        synth.handle(&event.message);
+    }
+
+    let tail_end = rendered + frame_at(settings.max_tail_ms as u64 * 1000);
+    while !synth.is_silent() && rendered < tail_end {
+        let frames = RENDER_BLOCK.min(tail_end - rendered);
+        render_frames(&mut synth, frames, &mut block, &mut output)?;
+        rendered += frames;
+    }
+
+    Ok(())
+}
+
+/// Renders `frames` frames into `output`, a block at a time.
+fn render_frames(
+    synth: &mut Synth,
+    frames: usize,
+    block: &mut [f32],
+    output: &mut impl FnMut(&[f32]) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut remaining = frames;
+    while remaining > 0 {
+        let len = remaining.min(block.len() / 2) * 2;
+        synth.render(&mut block[..len]);
+        output(&block[..len])?;
+        remaining -= len / 2;
+    }
+    Ok(())
+}
+
+/// Path for the audio of `path`, next to it, e.g. `take.wav`, numbered if
+/// that file already exists.
+pub fn rendered_path(path: &Path) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map_or("recording".into(), |stem| stem.to_string_lossy());
+
+    let mut rendered = path.with_file_name(format!("{}.wav", stem));
+    let mut count = 1;
+    while rendered.exists() {
+        count += 1;
+        rendered = path.with_file_name(format!("{}-{}.wav", stem, count));
+    }
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use midly::{
+        num::{u15, u28, u4, u7},
+        Format, Header, MetaMessage, MidiMessage, Timing, TrackEvent, TrackEventKind,
+    };
+
+    use super::*;
+
+    /// A file playing A4 for `length` ticks, a quarter note being 480 ticks
+    /// and half a second long.
+    fn smf(length: u32) -> Smf<'static> {
+        let key = u7::new(69);
+        let midi = |delta: u32, message| TrackEvent {
+            delta: u28::new(delta),
+            kind: TrackEventKind::Midi {
+                channel: u4::new(0),
+                message,
+            },
+        };
+
+        let mut smf = Smf::new(Header::new(
+            Format::SingleTrack,
+            Timing::Metrical(u15::new(480)),
+        ));
+        smf.tracks.push(vec![
+            midi(
+                0,
+                MidiMessage::NoteOn {
+                    key,
+                    vel: u7::new(100),
+                },
+            ),
+            midi(
+                length,
+                MidiMessage::NoteOff {
+                    key,
+                    vel: u7::new(0),
+                },
+            ),
+            TrackEvent {
+                delta: u28::new(0),
+                kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+            },
+        ]);
+        smf
+    }
+
+    fn render(smf: &Smf, settings: &SynthSettings) -> Vec<f32> {
+        let mut samples = vec![];
+        render_smf(smf, settings, Instrument::Piano, |block| {
+            samples.extend_from_slice(block);
+            Ok(())
+        })
+        .unwrap();
+        samples
+    }
+
+    #[test]
+    fn renders_deterministically() {
+        let settings = SynthSettings::default();
+        let samples = render(&smf(480), &settings);
+
+        assert_eq!(samples, render(&smf(480), &settings));
+        assert!(samples.iter().any(|sample| sample.abs() > 0.01));
+        assert!(samples.iter().all(|sample| sample.abs() <= 1.0));
+    }
+
+    #[test]
+    fn renders_the_song_and_its_tail() {
+        let settings = SynthSettings {
+            max_tail_ms: 1000,
+            ..SynthSettings::default()
+        };
+        let frames = render(&smf(480), &settings).len() / 2;
+
+        // Half a second of the note, then at most a second of release.
+        assert!(frames > 22_050);
+        assert!(frames <= 22_050 + 44_100);
+    }
+
+    #[test]
+    fn renders_nothing_for_an_empty_file() {
+        let smf = Smf::new(Header::new(
+            Format::SingleTrack,
+            Timing::Metrical(u15::new(480)),
+        ));
+
+        assert!(render(&smf, &SynthSettings::default()).is_empty());
+    }
+
+    #[test]
+    fn plays_notes_at_their_pitch() {
+        let mut synth = Synth::new(&SynthSettings::default(), Instrument::Piano);
+        synth.handle(&[0x90, 69, 100]);
+        let mut samples = vec![0.0; 4410 * 2];
+        synth.render(&mut samples);
+
+        // A4 is 440 Hz, so its fundamental repeats every 100.2 frames.
+        let left = samples.iter().step_by(2).copied().collect::<Vec<_>>();
+        let correlation = |lag: usize| {
+            (0..2000)
+                .map(|index| left[2000 + index] * left[2000 + index + lag])
+                .sum::<f32>()
+        };
+        let period = (60..200)
+            .max_by(|a, b| correlation(*a).total_cmp(&correlation(*b)))
+            .unwrap();
+        assert_eq!(period, 100);
+    }
+
+    #[test]
+    fn sustain_holds_released_notes() {
+        let mut synth = Synth::new(&SynthSettings::default(), Instrument::Piano);
+        let mut samples = vec![0.0; 44_100 * 2];
+
+        synth.handle(&[0xB0, 64, 127]);
+        synth.handle(&[0x90, 60, 100]);
+        synth.handle(&[0x80, 60, 0]);
+        synth.render(&mut samples);
+        assert!(!synth.is_silent());
+
+        synth.handle(&[0xB0, 64, 0]);
+        synth.render(&mut samples);
+        assert!(synth.is_silent());
+    }
+}
@@ -0,0 +1,303 @@
+use midly::{MetaMessage, Smf, Timing, TrackEventKind};
+
+/// Tempo of a metrical file until the first Tempo event, i.e. 120 BPM.
+const DEFAULT_MICROS_PER_QUARTER: u64 = 500_000;
+
+#[derive(Debug, Clone, Copy)]
+pub struct TempoSegment {
+    tick: u64,
+    micros: u64,
+    micros_per_quarter: u64,
+}
+
+/// Converts tick positions of a MIDI file into real time.
+///
+/// Metrical files are converted through every Tempo event in the file, so
+/// mid-song tempo changes are honoured. Timecode files have a fixed tick
+/// length and ignore Tempo events.
+#[derive(Debug, Clone)]
+pub enum TempoMap {
+    Metrical {
+        ticks_per_quarter: u64,
+        segments: Vec<TempoSegment>,
+    },
+    Timecode {
+        ticks_per_second: f64,
+    },
+}
+
+impl TempoMap {
+    pub fn from_smf(smf: &Smf) -> Self {
+        let ticks_per_quarter = match smf.header.timing {
+            Timing::Metrical(ticks_per_quarter) => ticks_per_quarter.as_int().max(1) as u64,
+            Timing::Timecode(fps, subframes) => {
+                return TempoMap::Timecode {
+                    ticks_per_second: fps.as_f32() as f64 * subframes as f64,
+                };
+            }
+        };
+
+        // Format 1 files keep their tempo changes in the first track, but
+        // some files spread them around, so collect them from every track.
+        let mut tempo_changes = smf
+            .tracks
+            .iter()
+            .flat_map(|track| {
+                let mut tick = 0u64;
+                track.iter().filter_map(move |event| {
+                    tick += event.delta.as_int() as u64;
+                    match event.kind {
+                        TrackEventKind::Meta(MetaMessage::Tempo(tempo)) => {
+                            Some((tick, tempo.as_int() as u64))
+                        }
+                        _ => None,
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        tempo_changes.sort_by_key(|(tick, _)| *tick);
+
+        let mut segments = vec![TempoSegment {
+            tick: 0,
+            micros: 0,
+            micros_per_quarter: DEFAULT_MICROS_PER_QUARTER,
+        }];
+
+        for (tick, micros_per_quarter) in tempo_changes {
+            let last = *segments.last().unwrap();
+            let segment = TempoSegment {
+                tick,
+                micros: last.micros
+                    + (tick - last.tick) * last.micros_per_quarter / ticks_per_quarter,
+                micros_per_quarter,
+            };
+
+            if last.tick == tick {
+                *segments.last_mut().unwrap() = segment;
+            } else {
+                segments.push(segment);
+            }
+        }
+
+        TempoMap::Metrical {
+            ticks_per_quarter,
+            segments,
+        }
+    }
+
+    pub fn tick_to_micros(&self, tick: u64) -> u64 {
+        match self {
+            TempoMap::Metrical {
+                ticks_per_quarter,
+                segments,
+            } => {
+                let index = segments.partition_point(|segment| segment.tick <= tick) - 1;
+                let segment = &segments[index];
+                segment.micros
+                    + (tick - segment.tick) * segment.micros_per_quarter / ticks_per_quarter
+            }
+            TempoMap::Timecode { ticks_per_second } => {
+                (tick as f64 * 1_000_000.0 / ticks_per_second).round() as u64
+            }
+        }
+    }
+
+    /// Inverse of `tick_to_micros`, rounded to the nearest tick.
+    pub fn micros_to_tick(&self, micros: u64) -> u64 {
+        match self {
+            TempoMap::Metrical {
+                ticks_per_quarter,
+                segments,
+            } => {
+                let index = segments.partition_point(|segment| segment.micros <= micros) - 1;
+                let segment = &segments[index];
+                let micros_per_quarter = segment.micros_per_quarter.max(1);
+                segment.tick
+                    + ((micros - segment.micros) * ticks_per_quarter + micros_per_quarter / 2)
+                        / micros_per_quarter
+            }
+            TempoMap::Timecode { ticks_per_second } => {
+                (micros as f64 * ticks_per_second / 1_000_000.0).round() as u64
+            }
+        }
+    }
+}
+
+/// Start tick of every bar in a metrical file, following its TimeSignature
+/// events and assuming 4/4 until the first one. Timecode files have no bars.
+pub fn bar_ticks(smf: &Smf) -> Vec<u64> {
+    let Timing::Metrical(ticks_per_quarter) = smf.header.timing else {
+        return vec![];
+    };
+    let ticks_per_quarter = ticks_per_quarter.as_int().max(1) as u64;
+    // Denominators beyond 128, and signatures that leave no ticks in a bar,
+    // are malformed and read as 4/4.
+    let ticks_per_bar = |numerator: u8, denominator_pow: u8| {
+        let ticks = match denominator_pow {
+            0..=7 => (ticks_per_quarter * 4 * numerator as u64) >> denominator_pow,
+            _ => 0,
+        };
+        if ticks > 0 {
+            ticks
+        } else {
+            ticks_per_quarter * 4
+        }
+    };
+
+    let mut end_tick = 0;
+    let mut signatures = vec![(0, ticks_per_bar(4, 2))];
+    for track in &smf.tracks {
+        let mut tick = 0u64;
+        for event in track {
+            tick += event.delta.as_int() as u64;
+            if let TrackEventKind::Meta(MetaMessage::TimeSignature(
+                numerator,
+                denominator_pow,
+                ..,
+            )) = event.kind
+            {
+                signatures.push((tick, ticks_per_bar(numerator, denominator_pow)));
+            }
+        }
+        end_tick = end_tick.max(tick);
+    }
+    // Stable sort, so the last signature at any tick wins below.
+    signatures.sort_by_key(|(tick, _)| *tick);
+
+    let mut bars = Vec::new();
+    let mut signature = 0;
+    let mut tick = 0;
+    while tick <= end_tick {
+        while signatures
+            .get(signature + 1)
+            .is_some_and(|(change, _)| *change <= tick)
+        {
+            signature += 1;
+        }
+        bars.push(tick);
+
+        // A time signature change in the middle of a bar starts a new bar.
+        let bar_end = tick + signatures[signature].1;
+        tick = match signatures.get(signature + 1) {
+            Some((change, _)) if *change < bar_end => *change,
+            _ => bar_end,
+        };
+    }
+
+    bars
+}
+
+#[cfg(test)]
+mod tests {
+    use midly::{
+        num::{u15, u24, u28},
+        Format, Fps, Header, TrackEvent,
+    };
+
+    use super::*;
+
+    /// A single-track file with `events` at absolute ticks, ending at `end`.
+    fn smf(timing: Timing, events: &[(u64, MetaMessage<'static>)], end: u64) -> Smf<'static> {
+        let mut smf = Smf::new(Header::new(Format::SingleTrack, timing));
+        let mut last = 0;
+        let mut track = vec![];
+        for (tick, message) in events.iter().chain([(end, MetaMessage::EndOfTrack)].iter()) {
+            track.push(TrackEvent {
+                delta: u28::new((tick - last) as u32),
+                kind: TrackEventKind::Meta(*message),
+            });
+            last = *tick;
+        }
+        smf.tracks.push(track);
+        smf
+    }
+
+    fn metrical() -> Timing {
+        Timing::Metrical(u15::new(480))
+    }
+
+    #[test]
+    fn follows_tempo_changes() {
+        let smf = smf(
+            metrical(),
+            &[(960, MetaMessage::Tempo(u24::new(250_000)))],
+            1920,
+        );
+        let tempo_map = TempoMap::from_smf(&smf);
+
+        assert_eq!(tempo_map.tick_to_micros(480), 500_000);
+        assert_eq!(tempo_map.tick_to_micros(960), 1_000_000);
+        assert_eq!(tempo_map.tick_to_micros(1440), 1_250_000);
+        assert_eq!(tempo_map.micros_to_tick(1_250_000), 1440);
+        assert_eq!(tempo_map.micros_to_tick(500_000), 480);
+    }
+
+    #[test]
+    fn tempo_at_the_start_replaces_the_default() {
+        let smf = smf(
+            metrical(),
+            &[(0, MetaMessage::Tempo(u24::new(1_000_000)))],
+            480,
+        );
+
+        assert_eq!(TempoMap::from_smf(&smf).tick_to_micros(480), 1_000_000);
+    }
+
+    #[test]
+    fn timecode_ignores_tempo() {
+        let smf = smf(
+            Timing::Timecode(Fps::Fps25, 40),
+            &[(0, MetaMessage::Tempo(u24::new(1_000_000)))],
+            1000,
+        );
+        let tempo_map = TempoMap::from_smf(&smf);
+
+        assert_eq!(tempo_map.tick_to_micros(500), 500_000);
+        assert_eq!(tempo_map.micros_to_tick(500_000), 500);
+        assert_eq!(bar_ticks(&smf), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn bars_follow_time_signatures() {
+        let smf = smf(
+            metrical(),
+            &[(1920, MetaMessage::TimeSignature(3, 2, 24, 8))],
+            4000,
+        );
+
+        assert_eq!(bar_ticks(&smf), vec![0, 1920, 3360]);
+    }
+
+    #[test]
+    fn malformed_time_signature_falls_back_to_four_four() {
+        for (numerator, denominator_pow) in [(3, 200), (3, 20), (3, 8), (0, 2)] {
+            let smf = smf(
+                metrical(),
+                &[(
+                    0,
+                    MetaMessage::TimeSignature(numerator, denominator_pow, 24, 8),
+                )],
+                4000,
+            );
+
+            assert_eq!(
+                bar_ticks(&smf),
+                vec![0, 1920, 3840],
+                "{}/2^{}",
+                numerator,
+                denominator_pow
+            );
+        }
+    }
+
+    #[test]
+    fn time_signature_in_the_middle_of_a_bar_starts_a_new_one() {
+        let smf = smf(
+            metrical(),
+            &[(960, MetaMessage::TimeSignature(6, 3, 24, 8))],
+            3000,
+        );
+
+        assert_eq!(bar_ticks(&smf), vec![0, 960, 2400]);
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+
+use midly::{live::LiveEvent, num::u4, MidiMessage};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageKind {
+    Note,
+    PolyAftertouch,
+    ControlChange,
+    ProgramChange,
+    ChannelPressure,
+    PitchBend,
+}
+
+impl MessageKind {
+    fn of(message: &MidiMessage) -> Self {
+        match message {
+            MidiMessage::NoteOn { .. } | MidiMessage::NoteOff { .. } => MessageKind::Note,
+            MidiMessage::Aftertouch { .. } => MessageKind::PolyAftertouch,
+            MidiMessage::Controller { .. } => MessageKind::ControlChange,
+            MidiMessage::ProgramChange { .. } => MessageKind::ProgramChange,
+            MidiMessage::ChannelAftertouch { .. } => MessageKind::ChannelPressure,
+            MidiMessage::PitchBend { .. } => MessageKind::PitchBend,
+        }
+    }
+}
+
+/// How live input is forwarded straight to the MIDI output.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct ThruSettings {
+    pub enabled: bool,
+    /// Output channel for input channels, both from 0 to 15. Channels that
+    /// are not listed keep their number.
+    pub channel_map: HashMap<u8, u8>,
+    /// Kinds of messages that are not forwarded.
+    pub blocked: Vec<MessageKind>,
+}
+
+impl ThruSettings {
+    pub fn is_valid(&self) -> bool {
+        self.channel_map
+            .iter()
+            .all(|(input, output)| *input < 16 && *output < 16)
+    }
+
+    /// Raw bytes to send to the output for `event`, or `None` if it is not
+    /// forwarded. Only channel messages are remapped or filtered; anything
+    /// else, such as SysEx, is forwarded as it is.
+    pub fn route(&self, event: LiveEvent) -> Option<Vec<u8>> {
+        if !self.enabled {
+            return None;
+        }
+        let event = match event {
+            LiveEvent::Midi { channel, message } => {
+                if self.blocked.contains(&MessageKind::of(&message)) {
+                    return None;
+                }
+                let channel = self
+                    .channel_map
+                    .get(&channel.as_int())
+                    .map_or(channel, |channel| u4::new(*channel));
+                LiveEvent::Midi { channel, message }
+            }
+            event => event,
+        };
+
+        let mut buf = Vec::with_capacity(3);
+        event
+            .write(&mut buf)
+            .expect("Error while writing MIDI event");
+        Some(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use midly::{
+        live::SystemCommon,
+        num::{u14, u7},
+        PitchBend,
+    };
+
+    use super::*;
+
+    fn settings(channel_map: &[(u8, u8)], blocked: &[MessageKind]) -> ThruSettings {
+        ThruSettings {
+            enabled: true,
+            channel_map: channel_map.iter().copied().collect(),
+            blocked: blocked.to_vec(),
+        }
+    }
+
+    fn midi(channel: u8, message: MidiMessage) -> LiveEvent<'static> {
+        LiveEvent::Midi {
+            channel: u4::new(channel),
+            message,
+        }
+    }
+
+    fn note_on(key: u8, vel: u8) -> MidiMessage {
+        MidiMessage::NoteOn {
+            key: u7::new(key),
+            vel: u7::new(vel),
+        }
+    }
+
+    fn sustain(value: u8) -> MidiMessage {
+        MidiMessage::Controller {
+            controller: u7::new(64),
+            value: u7::new(value),
+        }
+    }
+
+    #[test]
+    fn forwards_nothing_when_disabled() {
+        let settings = ThruSettings::default();
+
+        assert_eq!(settings.route(midi(0, note_on(60, 100))), None);
+    }
+
+    #[test]
+    fn remaps_channels_of_notes_and_controllers() {
+        let settings = settings(&[(0, 3), (1, 0)], &[]);
+
+        assert_eq!(
+            settings.route(midi(0, note_on(60, 100))),
+            Some(vec![0x93, 60, 100])
+        );
+        assert_eq!(
+            settings.route(midi(1, sustain(127))),
+            Some(vec![0xB0, 64, 127])
+        );
+        // Channels that are not listed keep their number.
+        assert_eq!(
+            settings.route(midi(5, note_on(64, 90))),
+            Some(vec![0x95, 64, 90])
+        );
+    }
+
+    #[test]
+    fn drops_blocked_kinds() {
+        let settings = settings(
+            &[(0, 3)],
+            &[MessageKind::ControlChange, MessageKind::PitchBend],
+        );
+
+        assert_eq!(settings.route(midi(0, sustain(127))), None);
+        assert_eq!(
+            settings.route(midi(
+                0,
+                MidiMessage::PitchBend {
+                    bend: PitchBend(u14::new(0x2000)),
+                },
+            )),
+            None
+        );
+        assert_eq!(
+            settings.route(midi(0, note_on(60, 100))),
+            Some(vec![0x93, 60, 100])
+        );
+    }
+
+    #[test]
+    fn leaves_sysex_untouched() {
+        let settings = settings(&[(0, 3)], &[MessageKind::Note]);
+        let data = u7::slice_from_int(&[0x7E, 0x00, 0x09, 0x01]);
+
+        assert_eq!(
+            settings.route(LiveEvent::Common(SystemCommon::SysEx(data))),
+            Some(vec![0xF0, 0x7E, 0x00, 0x09, 0x01, 0xF7])
+        );
+    }
+
+    #[test]
+    fn rejects_channels_out_of_range() {
+        assert!(settings(&[(15, 0)], &[]).is_valid());
+        assert!(!settings(&[(16, 0)], &[]).is_valid());
+        assert!(!settings(&[(0, 16)], &[]).is_valid());
+    }
+}
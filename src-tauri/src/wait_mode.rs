@@ -0,0 +1,191 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::playback::{PlaybackEvent, Song, TrackSelection};
+
+/// Notes starting within this many microseconds of each other form one chord.
+const CHORD_WINDOW: u64 = 40_000;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Hand {
+    Left,
+    Right,
+    #[default]
+    Both,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct WaitModeSettings {
+    /// Which hand the student plays; playback waits for these notes only.
+    pub hand: Hand,
+    /// Tracks played by the left hand.
+    pub left_hand_tracks: Vec<usize>,
+    /// Tracks played by the right hand.
+    pub right_hand_tracks: Vec<usize>,
+    /// Notes on other tracks are given to the right hand from this key up.
+    pub split_key: u8,
+}
+
+impl Default for WaitModeSettings {
+    fn default() -> Self {
+        WaitModeSettings {
+            hand: Hand::Both,
+            left_hand_tracks: vec![],
+            right_hand_tracks: vec![],
+            split_key: 60,
+        }
+    }
+}
+
+impl WaitModeSettings {
+    fn hand_of(&self, track: usize, key: u8) -> Hand {
+        if self.left_hand_tracks.contains(&track) {
+            Hand::Left
+        } else if self.right_hand_tracks.contains(&track) {
+            Hand::Right
+        } else if key < self.split_key {
+            Hand::Left
+        } else {
+            Hand::Right
+        }
+    }
+
+    /// Whether the event is a note the student is expected to play.
+    fn is_student_note(&self, event: &PlaybackEvent) -> bool {
+        let is_note = matches!(event.message[0] & 0xF0, 0x80 | 0x90 | 0xA0);
+        is_note
+            && (self.hand == Hand::Both || self.hand_of(event.track, event.message[1]) == self.hand)
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct WaitingForNotesEvent {
+    keys: Vec<u8>,
+    position_ms: u64,
+}
+
+#[derive(Debug)]
+struct Chord {
+    time: u64,
+    /// (channel, key) of every note in the chord, before transposition.
+    notes: Vec<(u8, u8)>,
+}
+
+/// Tracks the chords the student has to play and the keys they are holding.
+#[derive(Debug)]
+pub struct WaitMode {
+    settings: WaitModeSettings,
+    chords: Vec<Chord>,
+    next_chord: usize,
+    /// Keys the student has to play before playback continues; empty while
+    /// playback is not waiting.
+    expected: Vec<u8>,
+    /// Keys currently held on the live input.
+    held: HashSet<u8>,
+    /// Keys pressed since playback started waiting.
+    pressed: HashSet<u8>,
+}
+
+impl WaitMode {
+    pub fn new(song: &Song, tracks: &TrackSelection, settings: WaitModeSettings) -> Self {
+        let mut chords: Vec<Chord> = Vec::new();
+
+        let student_notes = song.timeline.iter().filter(|event| {
+            event.is_note_on && tracks.is_played(event.track) && settings.is_student_note(event)
+        });
+        for event in student_notes {
+            let note = (event.message[0] & 0x0F, event.message[1]);
+            match chords.last_mut() {
+                Some(chord) if event.time - chord.time <= CHORD_WINDOW => {
+                    if !chord.notes.contains(&note) {
+                        chord.notes.push(note);
+                    }
+                }
+                _ => chords.push(Chord {
+                    time: event.time,
+                    notes: vec![note],
+                }),
+            }
+        }
+
+        WaitMode {
+            settings,
+            chords,
+            next_chord: 0,
+            expected: vec![],
+            held: HashSet::new(),
+            pressed: HashSet::new(),
+        }
+    }
+
+    pub fn is_student_note(&self, event: &PlaybackEvent) -> bool {
+        self.settings.is_student_note(event)
+    }
+
+    /// Skips to the first chord at or after `position`.
+    pub fn rewind(&mut self, position: u64) {
+        self.next_chord = self.chords.partition_point(|chord| chord.time < position);
+        self.expected.clear();
+    }
+
+    pub fn next_chord_time(&self) -> Option<u64> {
+        self.chords.get(self.next_chord).map(|chord| chord.time)
+    }
+
+    /// Starts waiting for the next chord, with its keys transposed by
+    /// `transpose_key`. Returns `None` if no key of the chord can be played,
+    /// in which case the chord is skipped.
+    pub fn start_waiting(
+        &mut self,
+        transpose_key: impl Fn(u8, u8) -> Option<u8>,
+    ) -> Option<WaitingForNotesEvent> {
+        let chord = self.chords.get(self.next_chord)?;
+
+        let mut expected = chord
+            .notes
+            .iter()
+            .filter_map(|(channel, key)| transpose_key(*channel, *key))
+            .collect::<Vec<_>>();
+        expected.sort();
+        expected.dedup();
+
+        if expected.is_empty() {
+            self.advance();
+            return None;
+        }
+
+        self.expected = expected;
+        self.pressed.clear();
+
+        Some(WaitingForNotesEvent {
+            keys: self.expected.clone(),
+            position_ms: chord.time / 1000,
+        })
+    }
+
+    /// Records a key on the live input, returning whether playback was
+    /// waiting and the expected keys have now all been played.
+    pub fn note_input(&mut self, key: u8, is_pressed: bool) -> bool {
+        if is_pressed {
+            self.held.insert(key);
+            self.pressed.insert(key);
+        } else {
+            self.held.remove(&key);
+        }
+
+        !self.expected.is_empty()
+            && self
+                .expected
+                .iter()
+                .all(|key| self.pressed.contains(key) || self.held.contains(key))
+    }
+
+    /// Moves on to the chord after the one being waited for.
+    pub fn advance(&mut self) {
+        self.next_chord += 1;
+        self.expected.clear();
+        self.pressed.clear();
+    }
+}
@@ -0,0 +1,133 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+/// Most bytes of samples a WAV file holds, as the length of the whole RIFF
+/// chunk has to fit in 32 bits too.
+const MAX_DATA_LEN: u32 = u32::MAX - 36;
+
+/// Writes a 16-bit PCM WAV file block by block. The lengths in the header
+/// are filled in when it is finished.
+pub struct WavWriter {
+    writer: BufWriter<File>,
+    data_len: u32,
+}
+
+impl WavWriter {
+    pub fn create(path: &Path, sample_rate: u32, channels: u16) -> io::Result<Self> {
+        let block_align = channels as u32 * 2;
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&36u32.to_le_bytes())?;
+        writer.write_all(b"WAVE")?;
+
+        writer.write_all(b"fmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        // PCM
+        writer.write_all(&1u16.to_le_bytes())?;
+        writer.write_all(&channels.to_le_bytes())?;
+        writer.write_all(&sample_rate.to_le_bytes())?;
+        writer.write_all(&(sample_rate * block_align).to_le_bytes())?;
+        writer.write_all(&(block_align as u16).to_le_bytes())?;
+        writer.write_all(&16u16.to_le_bytes())?;
+
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+
+        Ok(WavWriter {
+            writer,
+            data_len: 0,
+        })
+    }
+
+    /// Appends interleaved samples from -1 to 1. Fails without writing any
+    /// of them if they do not fit in the file anymore.
+    pub fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        let data_len = u32::try_from(samples.len() * 2)
+            .ok()
+            .and_then(|len| self.data_len.checked_add(len))
+            .filter(|data_len| *data_len <= MAX_DATA_LEN)
+            .ok_or_else(|| io::Error::other("WAV file reached its size limit"))?;
+
+        for sample in samples {
+            let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+            self.writer.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_len = data_len;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_all(&(36 + self.data_len).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(40))?;
+        self.writer.write_all(&self.data_len.to_le_bytes())?;
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn u16_at(data: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn writes_header_and_samples() {
+        let path = std::env::temp_dir().join(format!("wav-test-{}.wav", std::process::id()));
+        let mut writer = WavWriter::create(&path, 44_100, 2).unwrap();
+        writer.write(&[0.0, 1.0]).unwrap();
+        writer.write(&[-1.0, 2.0]).unwrap();
+        writer.finish().unwrap();
+
+        let data = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(u32_at(&data, 4), 36 + 8);
+        assert_eq!(&data[8..16], b"WAVEfmt ");
+        assert_eq!(u16_at(&data, 20), 1);
+        assert_eq!(u16_at(&data, 22), 2);
+        assert_eq!(u32_at(&data, 24), 44_100);
+        assert_eq!(u32_at(&data, 28), 44_100 * 4);
+        assert_eq!(u16_at(&data, 32), 4);
+        assert_eq!(u16_at(&data, 34), 16);
+        assert_eq!(&data[36..40], b"data");
+        assert_eq!(u32_at(&data, 40), 8);
+
+        let samples = data[44..]
+            .chunks_exact(2)
+            .map(|sample| i16::from_le_bytes([sample[0], sample[1]]))
+            .collect::<Vec<_>>();
+        // Samples beyond full scale are clipped.
+        assert_eq!(samples, vec![0, i16::MAX, -i16::MAX, i16::MAX]);
+    }
+
+    #[test]
+    fn stops_at_the_size_limit() {
+        let path = std::env::temp_dir().join(format!("wav-full-test-{}.wav", std::process::id()));
+        let mut writer = WavWriter::create(&path, 44_100, 2).unwrap();
+        writer.data_len = MAX_DATA_LEN - 4;
+        writer.write(&[0.5, 0.5]).unwrap();
+        assert!(writer.write(&[0.5, 0.5]).is_err());
+        writer.finish().unwrap();
+
+        let data = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(u32_at(&data, 4), u32::MAX);
+        assert_eq!(u32_at(&data, 40), MAX_DATA_LEN);
+        assert_eq!(data.len(), 44 + 4);
+    }
+}
@@ -24,10 +24,12 @@ export function NoteShower() {
         e: Event<{
           channel: number;
           event_type: {
-            Note: [number, number, number];
+            Note?: [number, number, number];
           };
         }>
       ) => {
+        if (!e.payload.event_type.Note) return;
+
         console.log(e.payload.event_type.Note[1]);
 
         if (e.payload.event_type.Note[0] === 144) {
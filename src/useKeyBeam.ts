@@ -83,10 +83,12 @@ export function useKeyBeam<T extends HTMLElement>({
         e: Event<{
           channel: number;
           event_type: {
-            Note: [number, number, number];
+            Note?: [number, number, number];
           };
         }>
       ) => {
+        if (!e.payload.event_type.Note) return;
+
         let [noteState, note, _] = e.payload.event_type.Note;
         let isPressed = noteState === 144;
         if (pianoKey === note) {